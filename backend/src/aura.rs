@@ -0,0 +1,145 @@
+//! Support enemies that heal or shield the walkers around them.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::Aura,
+    graphics::SpriteType,
+    map::Constants,
+    mob::Mob,
+    walker::Walker,
+    world::{Map, World},
+};
+
+/// Component for walkers with a heal aura.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Healer {
+    pub countdown: u32,
+}
+
+/// Component for walkers with a shield aura.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ShieldBearer {
+    pub countdown: u32,
+}
+
+/// A temporary pool of hit points that absorbs damage before health does.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Shield {
+    pub amount: f32,
+    pub duration: u32,
+}
+
+/// Return every other walker within range of an aura centered on a walker.
+fn walkers_in_aura(
+    entity: u32,
+    aura: &Aura,
+    walkers: &Map<u32, Walker>,
+    mobs: &Map<u32, Mob>,
+) -> Vec<u32> {
+    let center = match mobs.get(&entity) {
+        Some(mob) => mob,
+        None => return Vec::new(),
+    };
+    let radius = aura.radius * f32::TILE_SIZE;
+    walkers
+        .keys()
+        .filter(|&&other| other != entity)
+        .filter(|&other| {
+            mobs.get(other)
+                .map(|mob| {
+                    let dx = mob.x - center.x;
+                    let dy = mob.y - center.y;
+                    dx * dx + dy * dy <= radius * radius
+                })
+                .unwrap_or(false)
+        })
+        .copied()
+        .collect()
+}
+
+impl World {
+    pub fn update_auras(&mut self) {
+        // Shields wear off over time
+        let mut trash = Vec::new();
+        for (&entity, shield) in &mut self.core_state.shields {
+            shield.duration = shield.duration.saturating_sub(1);
+            if shield.duration == 0 || shield.amount <= 0.0 {
+                trash.push(entity);
+            }
+        }
+        for entity in trash {
+            self.core_state.shields.remove(&entity);
+        }
+
+        for (&entity, healer) in &mut self.core_state.healers {
+            if healer.countdown > 0 {
+                healer.countdown -= 1;
+                continue;
+            }
+            let enemy_type = match self.core_state.walkers.get(&entity) {
+                Some(walker) => walker.enemy_type,
+                None => continue,
+            };
+            if let Some(aura) = &self.config.get_enemy(enemy_type).heal {
+                healer.countdown = aura.period;
+                let targets = walkers_in_aura(
+                    entity,
+                    aura,
+                    &self.core_state.walkers,
+                    &self.core_state.mobs,
+                );
+                for target in targets {
+                    if let Some(health) = self.core_state.health.get_mut(&target) {
+                        health.curr_health =
+                            (health.curr_health + aura.amount).min(health.max_health);
+                    }
+                }
+            }
+        }
+
+        for (&entity, bearer) in &mut self.core_state.shield_bearers {
+            if bearer.countdown > 0 {
+                bearer.countdown -= 1;
+                continue;
+            }
+            let enemy_type = match self.core_state.walkers.get(&entity) {
+                Some(walker) => walker.enemy_type,
+                None => continue,
+            };
+            if let Some(aura) = &self.config.get_enemy(enemy_type).shield {
+                bearer.countdown = aura.period;
+                let targets = walkers_in_aura(
+                    entity,
+                    aura,
+                    &self.core_state.walkers,
+                    &self.core_state.mobs,
+                );
+                for target in targets {
+                    // Shields don't stack. Refresh the stronger of the two.
+                    let shield = self.core_state.shields.entry(target).or_insert(Shield {
+                        amount: 0.0,
+                        duration: 0,
+                    });
+                    shield.amount = shield.amount.max(aura.amount);
+                    shield.duration = shield.duration.max(aura.duration);
+                }
+            }
+        }
+    }
+
+    pub fn dump_shields(&mut self, frame_fudge: f32) {
+        for entity in self.core_state.shields.keys() {
+            if let Some(mob) = self.core_state.mobs.get(entity) {
+                self.render_state.sprite_data.push(
+                    SpriteType::Shield as u8,
+                    mob.x + frame_fudge * (mob.x - mob.old_x),
+                    mob.y + frame_fudge * (mob.y - mob.old_y),
+                    0.0,
+                    0.4,
+                    0x88bbff,
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,313 @@
+//! Placing several towers at once. The player sketches a blueprint, sees
+//! which placements are valid and what the whole thing costs, then queues it
+//! in one go.
+//!
+//! Blueprints aren't part of the core state. Queueing one logs a build
+//! command for every tower, so replays don't need to know about them.
+
+use wasm_bindgen::prelude::*;
+
+use crate::{
+    map::{Tile, MAP_HEIGHT, MAP_WIDTH, TRUE_MAP_WIDTH},
+    tower::TESLA_INDEX,
+    world::World,
+};
+
+#[derive(Clone, Copy)]
+pub struct Placement {
+    pub row: usize,
+    pub col: usize,
+    pub tower_index: usize,
+}
+
+/// The blueprint's placements, stored so that they can be read from js.
+#[derive(Default)]
+pub struct BlueprintData {
+    row: Vec<u32>,
+    col: Vec<u32>,
+    tower_type: Vec<u32>,
+    valid: Vec<u8>,
+}
+
+impl BlueprintData {
+    fn clear(&mut self) {
+        self.row.clear();
+        self.col.clear();
+        self.tower_type.clear();
+        self.valid.clear();
+    }
+
+    fn push(&mut self, placement: &Placement, valid: bool) {
+        self.row.push(placement.row as u32);
+        self.col.push(placement.col as u32);
+        self.tower_type.push(placement.tower_index as u32);
+        self.valid.push(valid as u8);
+    }
+}
+
+/// How far from the mouse we look for a path tile for teslas to straddle.
+const STRADDLE_SEARCH_RADIUS: isize = 2;
+
+/// How far a tesla can be from the path tile it straddles. Paths are usually
+/// two tiles wide.
+const MAX_STRADDLE_REACH: isize = 3;
+
+/// Tiles on the line between two tiles, inclusive. There is one tile for
+/// every row or column along the longer axis, so the line has no gaps.
+fn line_tiles(from: (usize, usize), to: (usize, usize)) -> Vec<(usize, usize)> {
+    let d_row = to.0 as f32 - from.0 as f32;
+    let d_col = to.1 as f32 - from.1 as f32;
+    let steps = d_row.abs().max(d_col.abs()) as usize;
+    if steps == 0 {
+        return vec![from];
+    }
+    (0..=steps)
+        .map(|i| {
+            let t = i as f32 / steps as f32;
+            let row = from.0 as f32 + t * d_row;
+            let col = from.1 as f32 + t * d_col;
+            (row.round() as usize, col.round() as usize)
+        })
+        .collect()
+}
+
+fn visible_tile(map: &[Tile], row: isize, col: isize) -> Option<Tile> {
+    if row < 0 || col < 0 || row >= MAP_HEIGHT as isize || col >= MAP_WIDTH as isize {
+        return None;
+    }
+    map.get((row as usize + 2) * TRUE_MAP_WIDTH + col as usize + 2)
+        .copied()
+}
+
+/// First tile off the path when walking away from a path tile, if it is
+/// within reach.
+fn path_edge(
+    map: &[Tile],
+    row: isize,
+    col: isize,
+    d_row: isize,
+    d_col: isize,
+) -> Option<(usize, usize)> {
+    for i in 1..=MAX_STRADDLE_REACH {
+        let (row, col) = (row + i * d_row, col + i * d_col);
+        match visible_tile(map, row, col) {
+            Some(Tile::Empty) => return Some((row as usize, col as usize)),
+            Some(Tile::OutOfBounds) | None => return None,
+            Some(_) => {}
+        }
+    }
+    None
+}
+
+/// Find a pair of tiles on opposite sides of the path near the mouse, for
+/// teslas to arc across. Closer path tiles are better. Teslas arc across the
+/// path best when they are side by side across it rather than in line with
+/// it, so that orientation is tried first.
+fn tesla_straddle(
+    map: &[Tile],
+    can_build: impl Fn(usize, usize) -> bool,
+    row: usize,
+    col: usize,
+) -> Option<[(usize, usize); 2]> {
+    let (row, col) = (row as isize, col as isize);
+    let mut path_tiles = Vec::new();
+    for d_row in -STRADDLE_SEARCH_RADIUS..=STRADDLE_SEARCH_RADIUS {
+        for d_col in -STRADDLE_SEARCH_RADIUS..=STRADDLE_SEARCH_RADIUS {
+            match visible_tile(map, row + d_row, col + d_col) {
+                Some(Tile::Empty) | Some(Tile::OutOfBounds) | None => {}
+                Some(tile) => path_tiles.push((d_row * d_row + d_col * d_col, tile, d_row, d_col)),
+            }
+        }
+    }
+    // Stable, so ties go to the first tile in reading order
+    path_tiles.sort_by_key(|&(distance_squared, ..)| distance_squared);
+
+    for (_, tile, d_row, d_col) in path_tiles {
+        let (path_row, path_col) = (row + d_row, col + d_col);
+        let directions = match tile {
+            Tile::North
+            | Tile::South
+            | Tile::EastToNorth
+            | Tile::EastToSouth
+            | Tile::WestToNorth
+            | Tile::WestToSouth => [(0, 1), (1, 0)],
+            _ => [(1, 0), (0, 1)],
+        };
+        for &(d_row, d_col) in &directions {
+            let pair = (
+                path_edge(map, path_row, path_col, -d_row, -d_col),
+                path_edge(map, path_row, path_col, d_row, d_col),
+            );
+            if let (Some(a), Some(b)) = pair {
+                if can_build(a.0, a.1) && can_build(b.0, b.1) {
+                    return Some([a, b]);
+                }
+            }
+        }
+    }
+    None
+}
+
+impl World {
+    /// Whether a placement could be built right now. Only the first
+    /// placement on a tile counts.
+    fn placement_is_valid(&self, index: usize) -> bool {
+        let placement = &self.render_state.blueprint[index];
+        placement.row < MAP_HEIGHT
+            && placement.col < MAP_WIDTH
+            && placement.tower_index < self.config.common.len()
+            && self.query_can_build_tower(placement.row, placement.col, placement.tower_index)
+            && !self.render_state.blueprint[..index]
+                .iter()
+                .any(|other| (other.row, other.col) == (placement.row, placement.col))
+    }
+
+    fn add_placement(&mut self, row: usize, col: usize, tower_index: usize) {
+        self.render_state.blueprint.push(Placement {
+            row,
+            col,
+            tower_index,
+        });
+    }
+}
+
+#[wasm_bindgen]
+impl World {
+    pub fn clear_blueprint(&mut self) {
+        self.render_state.blueprint.clear();
+    }
+
+    pub fn add_to_blueprint(&mut self, row: usize, col: usize, tower_index: usize) {
+        self.add_placement(row, col, tower_index);
+    }
+
+    /// Add a tower on every tile of a line, like when the player drags the
+    /// mouse from one tile to another.
+    pub fn add_line_to_blueprint(
+        &mut self,
+        from_row: usize,
+        from_col: usize,
+        to_row: usize,
+        to_col: usize,
+        tower_index: usize,
+    ) {
+        for (row, col) in line_tiles((from_row, from_col), (to_row, to_col)) {
+            self.add_placement(row, col, tower_index);
+        }
+    }
+
+    /// Add a pair of teslas on either side of the path tile closest to a
+    /// tile. Returns false if there is no room for them.
+    pub fn add_tesla_straddle_to_blueprint(&mut self, row: usize, col: usize) -> bool {
+        let blueprint = &self.render_state.blueprint;
+        let can_build = |row, col| {
+            self.query_can_build_tower(row, col, TESLA_INDEX)
+                && !blueprint
+                    .iter()
+                    .any(|placement| (placement.row, placement.col) == (row, col))
+        };
+        match tesla_straddle(&self.level_state.map, can_build, row, col) {
+            Some(tiles) => {
+                for &(row, col) in &tiles {
+                    self.add_placement(row, col, TESLA_INDEX);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Total cost of the blueprint, in seconds of build time.
+    pub fn query_blueprint_cost(&self) -> f32 {
+        self.render_state
+            .blueprint
+            .iter()
+            .map(|placement| self.config.get_common(placement.tower_index).cost)
+            .sum::<f32>()
+            / self.core_state.difficulty.build_speed
+    }
+
+    pub fn query_blueprint_is_valid(&self) -> bool {
+        let count = self.render_state.blueprint.len();
+        count > 0 && (0..count).all(|i| self.placement_is_valid(i))
+    }
+
+    /// Queue every tower in the blueprint, or none of them if any placement
+    /// is invalid. Returns whether the blueprint was queued.
+    pub fn queue_blueprint(&mut self) -> bool {
+        if !self.query_blueprint_is_valid() {
+            return false;
+        }
+        let blueprint = std::mem::take(&mut self.render_state.blueprint);
+        for placement in blueprint {
+            self.queue_build_tower(placement.row, placement.col, placement.tower_index);
+        }
+        true
+    }
+
+    /// Fill the blueprint buffers and return how many placements there are.
+    pub fn dump_blueprint(&mut self) -> usize {
+        let count = self.render_state.blueprint.len();
+        let valid: Vec<bool> = (0..count).map(|i| self.placement_is_valid(i)).collect();
+        let data = &mut self.render_state.blueprint_data;
+        data.clear();
+        for (placement, valid) in self.render_state.blueprint.iter().zip(valid) {
+            data.push(placement, valid);
+        }
+        count
+    }
+
+    pub fn blueprint_row(&self) -> *const u32 {
+        self.render_state.blueprint_data.row.as_ptr()
+    }
+
+    pub fn blueprint_col(&self) -> *const u32 {
+        self.render_state.blueprint_data.col.as_ptr()
+    }
+
+    pub fn blueprint_tower_type(&self) -> *const u32 {
+        self.render_state.blueprint_data.tower_type.as_ptr()
+    }
+
+    pub fn blueprint_valid(&self) -> *const u8 {
+        self.render_state.blueprint_data.valid.as_ptr()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::map::{parse, MAP_0};
+
+    #[test]
+    fn lines_have_no_gaps() {
+        assert_eq!(line_tiles((3, 3), (3, 3)), vec![(3, 3)]);
+        assert_eq!(
+            line_tiles((0, 4), (0, 1)),
+            vec![(0, 4), (0, 3), (0, 2), (0, 1)]
+        );
+        let diagonal = line_tiles((0, 0), (2, 5));
+        assert_eq!(diagonal.len(), 6);
+        assert_eq!(diagonal.first(), Some(&(0, 0)));
+        assert_eq!(diagonal.last(), Some(&(2, 5)));
+        for pair in diagonal.windows(2) {
+            assert!(pair[1].0 - pair[0].0 <= 1);
+            assert_eq!(pair[1].1 - pair[0].1, 1);
+        }
+    }
+
+    #[test]
+    fn teslas_straddle_across_the_path() {
+        let map = parse(&MAP_0);
+
+        // Visible rows 10 and 11 have a path two tiles wide running south
+        // through cols 1 and 2
+        let straddle = tesla_straddle(&map, |_, _| true, 11, 1);
+        assert_eq!(straddle, Some([(11, 0), (11, 3)]));
+
+        // If one of those tiles is taken, try the next closest path tile
+        let straddle = tesla_straddle(&map, |row, col| (row, col) != (11, 0), 11, 1);
+        assert_eq!(straddle, Some([(10, 0), (10, 3)]));
+    }
+}
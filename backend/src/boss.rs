@@ -0,0 +1,110 @@
+//! Bosses and their phases.
+
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+use crate::{aura::Shield, config::Phase, health::DamageKind, world::World};
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Boss {
+    /// Index of the next phase in the boss's enemy type.
+    pub next_phase: usize,
+    pub immune_to: Vec<DamageKind>,
+}
+
+impl World {
+    /// Start any phases whose health thresholds bosses have dropped below.
+    /// This runs after damage is dealt, so a boss that takes a big hit can
+    /// start several phases at once.
+    pub fn update_boss_phases(&mut self) {
+        let entities: Vec<u32> = self.core_state.bosses.keys().copied().collect();
+        for entity in entities {
+            let fraction = match self.core_state.health.get(&entity) {
+                // Dead bosses don't start new phases
+                Some(health) if health.curr_health > 0.0 => health.curr_health / health.max_health,
+                _ => continue,
+            };
+            let enemy_type = match self.core_state.walkers.get(&entity) {
+                Some(walker) => walker.enemy_type,
+                None => continue,
+            };
+            while let Some(boss) = self.core_state.bosses.get_mut(&entity) {
+                let phases = &self.config.get_enemy(enemy_type).phases;
+                match phases.get(boss.next_phase) {
+                    Some(phase) if fraction <= phase.health_threshold => {
+                        boss.next_phase += 1;
+                        let phase = phase.clone();
+                        self.begin_phase(entity, &phase);
+                    }
+                    _ => break,
+                }
+            }
+        }
+    }
+
+    fn begin_phase(&mut self, entity: u32, phase: &Phase) {
+        if let Some(multiplier) = phase.speed_multiplier {
+            if let Some(walker) = self.core_state.walkers.get_mut(&entity) {
+                walker.speed *= multiplier;
+            }
+        }
+
+        if let Some(immune_to) = &phase.immune_to {
+            if let Some(boss) = self.core_state.bosses.get_mut(&entity) {
+                boss.immune_to = immune_to.clone();
+            }
+        }
+
+        match phase.shield {
+            Some(amount) if amount > 0.0 => {
+                // Boss shields last until they are broken
+                self.core_state.shields.insert(
+                    entity,
+                    Shield {
+                        amount,
+                        duration: u32::MAX,
+                    },
+                );
+            }
+            Some(_) => {
+                self.core_state.shields.remove(&entity);
+            }
+            None => {}
+        }
+
+        if let Some(minions) = &phase.minions {
+            let walker = self.core_state.walkers.get(&entity);
+            let mob = self.core_state.mobs.get(&entity);
+            if let (Some(walker), Some(mob)) = (walker, mob) {
+                let (wave, x, y) = (walker.wave, mob.x, mob.y);
+                let minion_type = self.config.enemy_index(minions);
+                self.spawn_along_path(minion_type, phase.minion_count, wave, x, y);
+            }
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl World {
+    /// Fraction of health remaining for the oldest boss on the field, or -1 if
+    /// there are no bosses.
+    pub fn query_boss_health(&self) -> f32 {
+        self.core_state
+            .bosses
+            .keys()
+            .find_map(|entity| self.core_state.health.get(entity))
+            .map(|health| (health.curr_health / health.max_health).max(0.0))
+            .unwrap_or(-1.0)
+    }
+
+    /// Name of the boss whose health is given by query_boss_health.
+    pub fn query_boss_name(&self) -> String {
+        self.core_state
+            .bosses
+            .keys()
+            .find(|&entity| self.core_state.health.contains_key(entity))
+            .and_then(|entity| self.core_state.walkers.get(entity))
+            .map(|walker| self.config.get_enemy(walker.enemy_type).name.clone())
+            .unwrap_or_default()
+    }
+}
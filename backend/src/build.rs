@@ -1,16 +1,21 @@
+use std::collections::VecDeque;
+
 use serde::{Deserialize, Serialize};
 use wasm_bindgen::prelude::*;
 
 use crate::{
-    factory::create_factory,
+    config::UpgradeEffect,
+    events::{Event, EventType},
+    factory::{build_upgrades, create_factory, Factory, FactoryMode},
     falcon::create_falcon_tower,
     graphics::SpriteType,
-    map::{tile_center, Tile, TRUE_MAP_WIDTH},
+    map::{tile_center, Terrain},
     missile::create_missile_tower,
     swallow::create_swallow_tower,
+    timeline::Command,
     tower::{
-        Tower, TowerStatus, FACTORY_INDEX, FALCON_INDEX, FIRE_INDEX, GAUSS_INDEX, MISSILE_INDEX,
-        SWALLOW_INDEX, TESLA_INDEX, TREE_INDEX,
+        create_tower, Tower, TowerStatus, FACTORY_INDEX, FALCON_INDEX, FIRE_INDEX, GAUSS_INDEX,
+        MISSILE_INDEX, SWALLOW_INDEX, TESLA_INDEX, TREE_INDEX,
     },
     world::{Map, World},
 };
@@ -24,6 +29,8 @@ pub struct BuildOrder {
     pub col: usize,
     pub tower_entity: u32,
     pub build_type: BuildType,
+    /// Paused orders don't make progress, but keep their place in the queue.
+    pub paused: bool,
 }
 
 impl BuildOrder {
@@ -34,19 +41,52 @@ impl BuildOrder {
                     tower.status = TowerStatus::Operational;
                 }
             }
-            BuildType::Upgrade { .. } => todo!(),
+            BuildType::Upgrade { upgrade_flag, .. } => {
+                if let Some(tower) = towers.get_mut(&self.tower_entity) {
+                    tower.status = TowerStatus::Operational;
+                    tower.upgrades |= upgrade_flag;
+                }
+            }
+        }
+    }
+
+    /// Event announcing that this order is done.
+    fn completed_event(&self, towers: &Map<u32, Tower>) -> Event {
+        let (x, y) = tile_center(self.row, self.col);
+        let (event_type, value) = match self.build_type {
+            BuildType::Tower => (
+                EventType::TowerCompleted,
+                towers
+                    .get(&self.tower_entity)
+                    .map(|tower| tower.type_index)
+                    .unwrap_or(0) as f32,
+            ),
+            BuildType::Upgrade { upgrade_flag, .. } => {
+                (EventType::UpgradeCompleted, upgrade_flag as f32)
+            }
+        };
+        Event {
+            event_type,
+            entity: self.tower_entity,
+            x,
+            y,
+            value,
         }
     }
 
     /// Update the tower status when we begin construction
-    fn notify_tower(&self, towers: &mut Map<u32, Tower>) {
+    pub fn notify_tower(&self, towers: &mut Map<u32, Tower>) {
         match self.build_type {
             BuildType::Tower => {
                 if let Some(tower) = towers.get_mut(&self.tower_entity) {
                     tower.status = TowerStatus::Building;
                 }
             }
-            BuildType::Upgrade { .. } => todo!(),
+            BuildType::Upgrade { .. } => {
+                if let Some(tower) = towers.get_mut(&self.tower_entity) {
+                    tower.status = TowerStatus::Upgrading;
+                }
+            }
         }
     }
 }
@@ -79,7 +119,10 @@ impl World {
             col,
             type_index: tower_index,
             range: base_tower.base_range,
+            rate_of_fire: base_tower.base_rate_of_fire,
+            burning: 1.0,
             status: crate::tower::TowerStatus::Queued,
+            upgrades: 0,
         });
     }
 
@@ -88,6 +131,12 @@ impl World {
     }
 
     pub fn queue_build_tower(&mut self, row: usize, col: usize, tower_index: usize) {
+        self.log_command(Command::BuildTower {
+            row,
+            col,
+            tower_index,
+        });
+
         // If there is a tower under construction in this spot already,
         // return and do nothing.
         for tower in self.core_state.towers.values() {
@@ -97,14 +146,7 @@ impl World {
         }
 
         // Likewise, don't build on illegal terrain
-        let true_row = row + 2;
-        let true_col = col + 2;
-        if Some(&Tile::Empty)
-            != self
-                .level_state
-                .map
-                .get(true_row * TRUE_MAP_WIDTH + true_col)
-        {
+        if !self.terrain_allows_tower(row, col, tower_index) {
             return;
         }
 
@@ -147,7 +189,11 @@ impl World {
         }
 
         if let Some(base_tower) = self.config.common.get(tower_index) {
-            let cost = (base_tower.cost * 60.0) as u32;
+            let mut cost = self.build_ticks(base_tower.cost);
+            if tower_index == TREE_INDEX && self.terrain_at(row, col) == Terrain::RichSoil {
+                cost /= self.config.terrain.rich_soil_growth;
+            }
+            let cost = cost as u32;
             let tower_entity = self.create_specific_tower(tower_index, row, col);
             self.core_state.build_queue.push_back(BuildOrder {
                 cost,
@@ -156,13 +202,77 @@ impl World {
                 col,
                 build_type: BuildType::Tower,
                 tower_entity,
+                paused: false,
             });
         }
     }
 
-    pub fn queue_upgrade(&mut self, row: usize, col: usize, upgrade_index: usize) {}
+    pub fn queue_upgrade(&mut self, row: usize, col: usize, upgrade_index: usize) {
+        self.log_command(Command::QueueUpgrade {
+            row,
+            col,
+            upgrade_index,
+        });
+
+        let tower_entity = match self.core_state.towers_by_pos.get(&(row, col)) {
+            Some(&entity) => entity,
+            None => return,
+        };
+        let tower = match self.core_state.towers.get(&tower_entity) {
+            Some(tower) => tower,
+            None => return,
+        };
+        let upgrade = match self
+            .config
+            .get_common(tower.type_index)
+            .upgrades
+            .get(upgrade_index)
+        {
+            Some(upgrade) if upgrade_index < 8 => upgrade,
+            _ => return,
+        };
+        let upgrade_flag = 1 << upgrade_index;
+        if tower.has_upgrade(upgrade_flag) {
+            return;
+        }
+
+        // Don't queue the same upgrade twice
+        for build_order in &self.core_state.build_queue {
+            if let BuildType::Upgrade {
+                upgrade_flag: queued_flag,
+                ..
+            } = build_order.build_type
+            {
+                if build_order.tower_entity == tower_entity && queued_flag == upgrade_flag {
+                    return;
+                }
+            }
+        }
+
+        use crate::world::RunState;
+        if let RunState::AutoPaused = self.run_state {
+            self.run_state = RunState::Playing;
+        }
+
+        let cost = self.build_ticks(upgrade.cost) as u32;
+        let can_build = tower_is_done(tower);
+        self.core_state.build_queue.push_back(BuildOrder {
+            cost,
+            progress: 0.0,
+            row,
+            col,
+            tower_entity,
+            build_type: BuildType::Upgrade {
+                can_build,
+                upgrade_flag,
+            },
+            paused: false,
+        });
+    }
 
     pub fn cancel_construction(&mut self, row: usize, col: usize) {
+        self.log_command(Command::CancelConstruction { row, col });
+
         let build_order = self
             .core_state
             .build_queue
@@ -170,14 +280,27 @@ impl World {
             .enumerate()
             .find(|(_, build_order)| (row, col) == (build_order.row, build_order.col));
         if let Some((index, build_order)) = build_order {
-            if let Some(&tower_entity) = self
-                .core_state
-                .towers_by_pos
-                .get(&(build_order.row, build_order.col))
-            {
-                self.destroy_tower(tower_entity);
+            match build_order.build_type {
+                BuildType::Tower => {
+                    // Cancelling the tower cancels any upgrades queued for it
+                    // too, like replacing it does
+                    let tower_entity = build_order.tower_entity;
+                    self.core_state
+                        .build_queue
+                        .retain(|build_order| (row, col) != (build_order.row, build_order.col));
+                    self.destroy_tower(tower_entity);
+                }
+                BuildType::Upgrade { .. } => {
+                    // Leave the tower as it was before the upgrade started
+                    let tower_entity = build_order.tower_entity;
+                    self.core_state.build_queue.remove(index);
+                    if let Some(tower) = self.core_state.towers.get_mut(&tower_entity) {
+                        if tower.status == TowerStatus::Upgrading {
+                            tower.status = TowerStatus::Operational;
+                        }
+                    }
+                }
             }
-            self.core_state.build_queue.remove(index);
 
             // Autopause
             if self.core_state.build_queue.is_empty() {
@@ -190,31 +313,80 @@ impl World {
     }
 }
 
-impl World {
-    pub fn progress_build(&mut self) {
+/// Whether a tower's initial construction is finished, so that upgrades can
+/// be built on it.
+pub fn tower_is_done(tower: &Tower) -> bool {
+    matches!(
+        tower.status,
+        TowerStatus::Operational | TowerStatus::Upgrading
+    )
+}
+
+/// Progress that the player adds to a build order in one tick. Bonus build
+/// time from the stockpile lets the player build up to twice as fast.
+fn player_build_rate(stockpile: &mut f32) -> f32 {
+    let bonus = stockpile.clamp(0.0, 1.0);
+    *stockpile -= bonus;
+    1.0 + bonus
+}
+
+/// The parts of the core state that construction reads and changes. Build
+/// time estimates step copies of them forward.
+pub struct Construction<'a> {
+    pub build_queue: &'a mut VecDeque<BuildOrder>,
+    pub towers: &'a mut Map<u32, Tower>,
+    pub towers_by_pos: &'a Map<(usize, usize), u32>,
+    pub factories: &'a mut Map<u32, Factory>,
+    pub stockpile: &'a mut f32,
+    /// Progress per tick that a factory adds to an order.
+    pub build_rate: f32,
+}
+
+impl Construction<'_> {
+    /// Make one tick of construction progress and remove the orders that are
+    /// done. Returns the completed orders, and whether every order in the
+    /// queue was completed.
+    pub fn step(&mut self) -> (Vec<BuildOrder>, bool) {
         // Ideally, we'd like the player to build a queued order that isn't
         // adjacent to a factory. That way, they aren't stealing work that
         // the factory could have done for free.
         // If all queued orders are adjacent to a factory, we build the first
-        // build order. That's the most predictable choice.
+        // build order that isn't paused. That's the most predictable choice.
 
         let mut found_non_adjacent_order = false;
 
+        // Upgrades wait for their towers
+        for build_order in self.build_queue.iter_mut() {
+            if let BuildType::Upgrade { can_build, .. } = &mut build_order.build_type {
+                *can_build = self
+                    .towers
+                    .get(&build_order.tower_entity)
+                    .map(tower_is_done)
+                    .unwrap_or(false);
+            }
+        }
+
+        let build_rate = self.build_rate;
         let mut completed_order_indeces = Vec::new();
-        for (i, build_order) in self.core_state.build_queue.iter_mut().enumerate() {
+        let mut completed_orders = Vec::new();
+        for (i, build_order) in self.build_queue.iter_mut().enumerate() {
             let north = (build_order.row.wrapping_sub(1), build_order.col);
             let west = (build_order.row, build_order.col.wrapping_sub(1));
             let south = (build_order.row + 1, build_order.col);
             let east = (build_order.row, build_order.col + 1);
-            let towers_by_pos = &mut self.core_state.towers_by_pos;
-            let towers = &mut self.core_state.towers;
-            let factories = &mut self.core_state.factories;
+            let towers_by_pos = self.towers_by_pos;
+            let towers = &mut *self.towers;
+            let factories = &mut *self.factories;
 
             if build_order.progress >= build_order.cost as f32 {
                 build_order.complete(towers);
+                completed_orders.push(build_order.clone());
                 completed_order_indeces.push(i);
                 continue;
             }
+            if build_order.paused {
+                continue;
+            }
 
             // Add progress from factory construction
             let mut found_factory = false;
@@ -222,18 +394,21 @@ impl World {
                 .iter()
                 .filter_map(|pos| towers_by_pos.get(pos))
                 .for_each(|entity| {
-                    if let Some(factory) = factories.get_mut(entity) {
-                        // Even queued factories should count. Ignoring them
-                        // just because they don't exist yet feels bad from the
-                        // player's perspective.
-                        found_factory = true;
-                        if let Some(Tower { status, .. }) = towers.get(entity) {
-                            if *status == TowerStatus::Operational && !factory.is_constructing {
-                                factory.is_constructing = true;
-                                build_order.notify_tower(towers);
-                                build_order.progress += 0.5;
+                    match factories.get_mut(entity) {
+                        Some(factory) if factory.mode == FactoryMode::Construct => {
+                            // Even queued factories should count. Ignoring them
+                            // just because they don't exist yet feels bad from
+                            // the player's perspective.
+                            found_factory = true;
+                            if let Some(Tower { status, .. }) = towers.get(entity) {
+                                if *status == TowerStatus::Operational && !factory.is_constructing {
+                                    factory.is_constructing = true;
+                                    build_order.notify_tower(towers);
+                                    build_order.progress += build_rate;
+                                }
                             }
                         }
+                        _ => {}
                     }
                 });
 
@@ -241,36 +416,82 @@ impl World {
             if !found_non_adjacent_order && !found_factory && build_order.build_type.can_build() {
                 found_non_adjacent_order = true;
                 build_order.notify_tower(towers);
-                build_order.progress += 1.0;
+                build_order.progress += player_build_rate(self.stockpile);
             }
         }
 
-        // Autopause
-        if !completed_order_indeces.is_empty()
-            && completed_order_indeces.len() == self.core_state.build_queue.len()
-        {
-            use crate::world::RunState;
-            if let RunState::Playing = self.run_state {
-                self.run_state = RunState::AutoPaused;
-            }
-        }
+        build_upgrades(self.build_queue, self.towers, self.factories, build_rate);
+
+        let finished_everything = !completed_order_indeces.is_empty()
+            && completed_order_indeces.len() == self.build_queue.len();
 
         for i in completed_order_indeces.into_iter().rev() {
-            self.core_state.build_queue.remove(i);
+            self.build_queue.remove(i);
         }
 
         if !found_non_adjacent_order {
-            if let Some(build_order) = self.core_state.build_queue.front_mut() {
+            let build_queue = &mut *self.build_queue;
+            if let Some(i) = build_queue
+                .iter()
+                .position(|build_order| !build_order.paused)
+            {
+                let build_order = &mut build_queue[i];
                 if build_order.progress >= build_order.cost as f32 {
-                    build_order.complete(&mut self.core_state.towers);
-                    self.core_state.build_queue.pop_front();
+                    build_order.complete(self.towers);
+                    completed_orders.push(build_order.clone());
+                    build_queue.remove(i);
                 } else if build_order.build_type.can_build() {
-                    build_order.notify_tower(&mut self.core_state.towers);
-                    build_order.progress += 1.0;
+                    build_order.notify_tower(self.towers);
+                    build_order.progress += player_build_rate(self.stockpile);
                 }
             }
         }
 
+        (completed_orders, finished_everything)
+    }
+}
+
+impl World {
+    /// Ticks it takes the player to build something that costs this many
+    /// seconds, on the current difficulty.
+    fn build_ticks(&self, cost: f32) -> f32 {
+        cost * 60.0 / self.core_state.difficulty.build_speed
+    }
+
+    pub fn progress_build(&mut self) {
+        let (completed_orders, finished_everything) = Construction {
+            build_queue: &mut self.core_state.build_queue,
+            towers: &mut self.core_state.towers,
+            towers_by_pos: &self.core_state.towers_by_pos,
+            factories: &mut self.core_state.factories,
+            stockpile: &mut self.core_state.stockpile,
+            build_rate: self.config.factory.build_rate,
+        }
+        .step();
+
+        // Autopause
+        if finished_everything {
+            use crate::world::RunState;
+            if let RunState::Playing = self.run_state {
+                self.run_state = RunState::AutoPaused;
+            }
+        }
+
+        let mut tower_completed = false;
+        for build_order in completed_orders {
+            match build_order.build_type {
+                BuildType::Tower => tower_completed = true,
+                BuildType::Upgrade { upgrade_flag, .. } => {
+                    self.apply_upgrade(build_order.tower_entity, upgrade_flag)
+                }
+            }
+            let event = build_order.completed_event(&self.core_state.towers);
+            self.emit(event);
+        }
+        if tower_completed {
+            self.refresh_synergies();
+        }
+
         self.rotate_factories();
         for factory in self.core_state.factories.values_mut() {
             factory.is_constructing = false;
@@ -282,6 +503,7 @@ impl World {
             self.core_state
                 .towers_by_pos
                 .remove(&(tower.row, tower.col));
+            self.core_state.stats.towers.remove(&entity);
             match tower.type_index {
                 i if i == SWALLOW_INDEX => {
                     if let Some(targeter) = self.core_state.swallow_targeters.remove(&entity) {
@@ -302,23 +524,47 @@ impl World {
                 i if i == FACTORY_INDEX => {}
                 _ => {}
             }
+            self.refresh_synergies();
+        }
+    }
+
+    /// Apply the effects of a finished upgrade.
+    fn apply_upgrade(&mut self, tower_entity: u32, upgrade_flag: u8) {
+        let type_index = match self.core_state.towers.get(&tower_entity) {
+            Some(tower) => tower.type_index,
+            None => return,
+        };
+        let upgrades = &self.config.get_common(type_index).upgrades;
+        let effect = match upgrades.get(upgrade_flag.trailing_zeros() as usize) {
+            Some(upgrade) => upgrade.effect.clone(),
+            None => return,
+        };
+        match effect {
+            UpgradeEffect::Swallows { count } => self.add_swallows(tower_entity, count),
+            UpgradeEffect::ClusterWarhead | UpgradeEffect::Homing | UpgradeEffect::Salvo { .. } => {
+                if let Some(spawner) = self.core_state.missile_spawners.get_mut(&tower_entity) {
+                    spawner.upgrade(&effect);
+                }
+            }
         }
     }
 
     fn create_specific_tower(&mut self, tower_index: usize, row: usize, col: usize) -> u32 {
         match tower_index {
-            i if i == SWALLOW_INDEX => create_swallow_tower(
-                &mut self.core_state.entity_ids,
-                row,
-                col,
-                &mut self.core_state.towers,
-                &mut self.core_state.towers_by_pos,
-                &mut self.core_state.swallow_targeters,
-                &mut self.core_state.swallows,
-                &mut self.core_state.mobs,
-                &mut self.core_state.build_queue,
-                &self.config,
-            ),
+            i if i == SWALLOW_INDEX => {
+                let entity = create_swallow_tower(
+                    &mut self.core_state.entity_ids,
+                    row,
+                    col,
+                    &mut self.core_state.towers,
+                    &mut self.core_state.towers_by_pos,
+                    &mut self.core_state.swallow_targeters,
+                    &mut self.core_state.build_queue,
+                    &self.config,
+                );
+                self.add_swallows(entity, self.config.swallow.count);
+                entity
+            }
             i if i == FALCON_INDEX => create_falcon_tower(
                 &mut self.core_state.entity_ids,
                 row,
@@ -332,7 +578,20 @@ impl World {
             ),
             i if i == TESLA_INDEX => 0,
             i if i == GAUSS_INDEX => 0,
-            i if i == FIRE_INDEX => 0,
+            i if i == FIRE_INDEX => {
+                let entity = self.core_state.entity_ids.next();
+                create_tower(
+                    row,
+                    col,
+                    entity,
+                    FIRE_INDEX,
+                    &mut self.core_state.towers,
+                    &mut self.core_state.towers_by_pos,
+                    &mut self.core_state.build_queue,
+                    &self.config,
+                );
+                entity
+            }
             i if i == MISSILE_INDEX => create_missile_tower(
                 self.core_state.entity_ids.next(),
                 row,
@@ -368,8 +627,3 @@ impl World {
         }
     }
 }
-
-// Behavior for building teslas: from the mouse, find the closest path tile.
-// Then try and straddle the path tile with teslas. There are some situations
-// where horizontal and vertical placement are both possible, so tiebreak
-// using mouse velocity?
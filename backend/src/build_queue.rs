@@ -0,0 +1,408 @@
+//! Letting the player manage the build queue: reordering orders, pausing
+//! them, and listing the whole queue for the UI along with estimates of when
+//! each order will be done.
+
+use std::collections::VecDeque;
+
+use wasm_bindgen::prelude::*;
+
+use crate::{
+    build::{BuildOrder, BuildType, Construction},
+    factory::Factory,
+    timeline::Command,
+    tower::Tower,
+    waves::TICKS_PER_SECOND,
+    world::{Map, World},
+};
+
+/// The build queue, stored so that it can be read from js.
+#[derive(Default)]
+pub struct BuildQueueData {
+    row: Vec<u32>,
+    col: Vec<u32>,
+    tower_type: Vec<u32>,
+    /// 0 for towers. Otherwise the upgrade flag.
+    upgrade: Vec<u8>,
+    progress: Vec<f32>,
+    cost: Vec<f32>,
+    /// Ticks from now until the order is done. Infinite if it never will be.
+    eta: Vec<f32>,
+    paused: Vec<u8>,
+}
+
+impl BuildQueueData {
+    fn clear(&mut self) {
+        self.row.clear();
+        self.col.clear();
+        self.tower_type.clear();
+        self.upgrade.clear();
+        self.progress.clear();
+        self.cost.clear();
+        self.eta.clear();
+        self.paused.clear();
+    }
+
+    fn push(&mut self, build_order: &BuildOrder, tower_type: usize, eta: f32) {
+        self.row.push(build_order.row as u32);
+        self.col.push(build_order.col as u32);
+        self.tower_type.push(tower_type as u32);
+        self.upgrade.push(match build_order.build_type {
+            BuildType::Tower => 0,
+            BuildType::Upgrade { upgrade_flag, .. } => upgrade_flag,
+        });
+        self.progress.push(build_order.progress);
+        self.cost.push(build_order.cost as f32);
+        self.eta.push(eta);
+        self.paused.push(build_order.paused as u8);
+    }
+}
+
+/// Longest that build time estimates look ahead, in ticks.
+const MAX_SIMULATED_TICKS: u32 = 10 * 60 * 60;
+
+/// How long estimates are reused for. Commands throw them away right away,
+/// but wave income can also change how fast the queue is built.
+const ETA_REFRESH_TICKS: u32 = TICKS_PER_SECOND;
+
+/// Build time estimates, kept between frames so that the queue isn't
+/// re-simulated every frame.
+pub struct BuildEtas {
+    /// The tick the estimates were made on.
+    tick: u32,
+    /// The tick each order will be completed on, by order key. Orders that
+    /// won't be completed are left out.
+    done_on: Vec<((usize, usize, u8), u32)>,
+}
+
+/// Identifies a build order. There is at most one tower order per tile, and
+/// the same upgrade can't be queued twice.
+fn order_key(build_order: &BuildOrder) -> (usize, usize, u8) {
+    let upgrade_flag = match build_order.build_type {
+        BuildType::Tower => 0,
+        BuildType::Upgrade { upgrade_flag, .. } => upgrade_flag,
+    };
+    (build_order.row, build_order.col, upgrade_flag)
+}
+
+/// Run construction forward on copies of the state. Returns how many ticks
+/// from now each order in the queue is completed on, or None if it won't be,
+/// eg. because it is paused.
+fn simulate_construction(
+    build_queue: &VecDeque<BuildOrder>,
+    towers: &Map<u32, Tower>,
+    towers_by_pos: &Map<(usize, usize), u32>,
+    factories: &Map<u32, Factory>,
+    stockpile: f32,
+    build_rate: f32,
+) -> Vec<Option<u32>> {
+    let keys: Vec<_> = build_queue.iter().map(order_key).collect();
+    let mut completions = vec![None; keys.len()];
+
+    let mut build_queue = build_queue.clone();
+    let mut towers = towers.clone();
+    let mut factories = factories.clone();
+    let mut stockpile = stockpile;
+    for tick in 0..MAX_SIMULATED_TICKS {
+        // Once the queue is empty, the game autopauses
+        if build_queue.is_empty() {
+            break;
+        }
+        let progress_before: f32 = build_queue.iter().map(|order| order.progress).sum();
+        let (completed_orders, _) = Construction {
+            build_queue: &mut build_queue,
+            towers: &mut towers,
+            towers_by_pos,
+            factories: &mut factories,
+            stockpile: &mut stockpile,
+            build_rate,
+        }
+        .step();
+        for factory in factories.values_mut() {
+            factory.is_constructing = false;
+        }
+
+        for build_order in &completed_orders {
+            let key = order_key(build_order);
+            if let Some(i) = keys.iter().position(|&other| other == key) {
+                completions[i] = Some(tick);
+            }
+        }
+
+        // If nothing happened, nothing ever will
+        let progress_after: f32 = build_queue.iter().map(|order| order.progress).sum();
+        if completed_orders.is_empty() && progress_after <= progress_before {
+            break;
+        }
+    }
+    completions
+}
+
+impl World {
+    /// Ticks from now that each order in the queue is completed on, reusing
+    /// the last estimates while they are fresh.
+    fn estimate_build_times(&mut self) -> Vec<Option<u32>> {
+        let tick = self.core_state.tick;
+        let fresh = match &self.render_state.build_etas {
+            Some(etas) => etas.tick <= tick && tick - etas.tick < ETA_REFRESH_TICKS,
+            None => false,
+        };
+        if !fresh {
+            let build_queue = &self.core_state.build_queue;
+            let completions = simulate_construction(
+                build_queue,
+                &self.core_state.towers,
+                &self.core_state.towers_by_pos,
+                &self.core_state.factories,
+                self.core_state.stockpile,
+                self.config.factory.build_rate,
+            );
+            let done_on = build_queue
+                .iter()
+                .zip(completions)
+                .filter_map(|(build_order, eta)| Some((order_key(build_order), tick + eta?)))
+                .collect();
+            self.render_state.build_etas = Some(BuildEtas { tick, done_on });
+        }
+
+        let done_on = match &self.render_state.build_etas {
+            Some(etas) => &etas.done_on,
+            None => return Vec::new(),
+        };
+        self.core_state
+            .build_queue
+            .iter()
+            .map(|build_order| {
+                let key = order_key(build_order);
+                done_on
+                    .iter()
+                    .find(|(other, _)| *other == key)
+                    .map(|&(_, done_on)| done_on.saturating_sub(tick))
+            })
+            .collect()
+    }
+}
+
+#[wasm_bindgen]
+impl World {
+    pub fn move_build_order_to_front(&mut self, index: usize) {
+        self.log_command(Command::MoveBuildOrderToFront { index });
+
+        let build_queue = &mut self.core_state.build_queue;
+        if let Some(build_order) = build_queue.remove(index) {
+            build_queue.push_front(build_order);
+        }
+    }
+
+    pub fn move_build_order_to_back(&mut self, index: usize) {
+        self.log_command(Command::MoveBuildOrderToBack { index });
+
+        let build_queue = &mut self.core_state.build_queue;
+        if let Some(build_order) = build_queue.remove(index) {
+            build_queue.push_back(build_order);
+        }
+    }
+
+    pub fn swap_build_orders(&mut self, a: usize, b: usize) {
+        self.log_command(Command::SwapBuildOrders { a, b });
+
+        let build_queue = &mut self.core_state.build_queue;
+        if a < build_queue.len() && b < build_queue.len() {
+            build_queue.swap(a, b);
+        }
+    }
+
+    pub fn toggle_build_order_paused(&mut self, index: usize) {
+        self.log_command(Command::ToggleBuildOrderPaused { index });
+
+        if let Some(build_order) = self.core_state.build_queue.get_mut(index) {
+            build_order.paused = !build_order.paused;
+        }
+    }
+
+    /// Ticks from now until the first order on a tile is completed, assuming
+    /// that the player doesn't change the queue. Infinite if there is no
+    /// order or it won't be completed.
+    pub fn query_build_eta(&mut self, row: usize, col: usize) -> f32 {
+        let etas = self.estimate_build_times();
+        self.core_state
+            .build_queue
+            .iter()
+            .zip(etas)
+            .find(|(build_order, _)| (row, col) == (build_order.row, build_order.col))
+            .and_then(|(_, eta)| eta)
+            .map(|eta| eta as f32)
+            .unwrap_or(f32::INFINITY)
+    }
+
+    /// Fill the build queue buffers and return how many orders there are. ETAs
+    /// are in ticks from now, like `query_build_eta`.
+    pub fn dump_build_queue(&mut self) -> usize {
+        let etas = self.estimate_build_times();
+        let data = &mut self.render_state.build_queue;
+        data.clear();
+        for (build_order, eta) in self.core_state.build_queue.iter().zip(etas) {
+            let tower_type = self
+                .core_state
+                .towers
+                .get(&build_order.tower_entity)
+                .map(|tower| tower.type_index)
+                .unwrap_or_default();
+            let eta = eta.map(|eta| eta as f32).unwrap_or(f32::INFINITY);
+            data.push(build_order, tower_type, eta);
+        }
+        self.core_state.build_queue.len()
+    }
+
+    pub fn build_queue_row(&self) -> *const u32 {
+        self.render_state.build_queue.row.as_ptr()
+    }
+
+    pub fn build_queue_col(&self) -> *const u32 {
+        self.render_state.build_queue.col.as_ptr()
+    }
+
+    pub fn build_queue_tower_type(&self) -> *const u32 {
+        self.render_state.build_queue.tower_type.as_ptr()
+    }
+
+    pub fn build_queue_upgrade(&self) -> *const u8 {
+        self.render_state.build_queue.upgrade.as_ptr()
+    }
+
+    pub fn build_queue_progress(&self) -> *const f32 {
+        self.render_state.build_queue.progress.as_ptr()
+    }
+
+    pub fn build_queue_cost(&self) -> *const f32 {
+        self.render_state.build_queue.cost.as_ptr()
+    }
+
+    pub fn build_queue_eta(&self) -> *const f32 {
+        self.render_state.build_queue.eta.as_ptr()
+    }
+
+    pub fn build_queue_paused(&self) -> *const u8 {
+        self.render_state.build_queue.paused.as_ptr()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        factory::FactoryMode,
+        tower::{TowerStatus, FACTORY_INDEX, SWALLOW_INDEX},
+    };
+
+    fn tower(row: usize, col: usize, type_index: usize, status: TowerStatus) -> Tower {
+        Tower {
+            row,
+            col,
+            range: 0.0,
+            rate_of_fire: 0.0,
+            burning: 1.0,
+            type_index,
+            status,
+            upgrades: 0,
+        }
+    }
+
+    fn tower_order(row: usize, col: usize, tower_entity: u32, cost: u32) -> BuildOrder {
+        BuildOrder {
+            cost,
+            progress: 0.0,
+            row,
+            col,
+            tower_entity,
+            build_type: BuildType::Tower,
+            paused: false,
+        }
+    }
+
+    struct Fixture {
+        build_queue: VecDeque<BuildOrder>,
+        towers: Map<u32, Tower>,
+        towers_by_pos: Map<(usize, usize), u32>,
+        factories: Map<u32, Factory>,
+    }
+
+    impl Fixture {
+        fn new() -> Fixture {
+            Fixture {
+                build_queue: VecDeque::new(),
+                towers: Map::default(),
+                towers_by_pos: Map::default(),
+                factories: Map::default(),
+            }
+        }
+
+        fn queue_tower(&mut self, row: usize, col: usize, entity: u32, cost: u32) {
+            let queued = tower(row, col, SWALLOW_INDEX, TowerStatus::Queued);
+            self.towers.insert(entity, queued);
+            self.towers_by_pos.insert((row, col), entity);
+            self.build_queue
+                .push_back(tower_order(row, col, entity, cost));
+        }
+
+        fn add_factory(&mut self, row: usize, col: usize, entity: u32) {
+            let factory = tower(row, col, FACTORY_INDEX, TowerStatus::Operational);
+            self.towers.insert(entity, factory);
+            self.towers_by_pos.insert((row, col), entity);
+            self.factories.insert(
+                entity,
+                Factory {
+                    rotation: 0.0,
+                    rotation_speed: 0.0,
+                    is_constructing: false,
+                    mode: FactoryMode::Construct,
+                },
+            );
+        }
+
+        fn simulate(&self) -> Vec<Option<u32>> {
+            simulate_construction(
+                &self.build_queue,
+                &self.towers,
+                &self.towers_by_pos,
+                &self.factories,
+                0.0,
+                0.5,
+            )
+        }
+    }
+
+    #[test]
+    fn player_builds_orders_one_after_another() {
+        let mut fixture = Fixture::new();
+        fixture.queue_tower(0, 0, 1, 10);
+        fixture.queue_tower(5, 5, 2, 10);
+
+        // An order is completed on the tick after it reaches its cost, and the
+        // player starts on the next order that same tick
+        assert_eq!(fixture.simulate(), vec![Some(10), Some(20)]);
+    }
+
+    #[test]
+    fn factories_build_alongside_the_player() {
+        let mut fixture = Fixture::new();
+        fixture.add_factory(0, 0, 1);
+        fixture.queue_tower(0, 1, 2, 10);
+        fixture.queue_tower(5, 5, 3, 10);
+
+        // The factory builds the adjacent order at 0.5 per tick while the
+        // player builds the other one. Once the player is free, they help out
+        // at 1 per tick, and finish the order as soon as it reaches its cost.
+        assert_eq!(fixture.simulate(), vec![Some(13), Some(10)]);
+    }
+
+    #[test]
+    fn paused_orders_are_never_completed() {
+        let mut fixture = Fixture::new();
+        fixture.queue_tower(0, 0, 1, 10);
+        fixture.queue_tower(5, 5, 2, 10);
+        fixture.build_queue[0].paused = true;
+
+        assert_eq!(fixture.simulate(), vec![None, Some(10)]);
+    }
+}
@@ -1,7 +1,7 @@
 //! Functions for finding collisions and intersections between geometric objects
 //! and between game entities.
 
-use crate::map::{has_border, in_bounds, Constants, Tile};
+use crate::map::{has_border, in_bounds, Constants, Terrain, Tile};
 
 /// Resolve collisions between a circular entity and the edges of the path
 /// in a certain map. If the entity overlaps a wall in multiple dimensions,
@@ -11,7 +11,13 @@ use crate::map::{has_border, in_bounds, Constants, Tile};
 ///
 /// Return the velocity that was applied to the entity to make it no longer
 /// collide.
-pub fn resolve_collisions(map: &[Tile], x: &mut f32, y: &mut f32, radius: f32) -> (f32, f32) {
+pub fn resolve_collisions(
+    map: &[Tile],
+    terrain: &[Terrain],
+    x: &mut f32,
+    y: &mut f32,
+    radius: f32,
+) -> (f32, f32) {
     if !in_bounds(*x, *y) {
         return (0.0, 0.0);
     }
@@ -24,11 +30,11 @@ pub fn resolve_collisions(map: &[Tile], x: &mut f32, y: &mut f32, radius: f32) -
     let border_x = (high_col as f32 - 2.0) * f32::TILE_SIZE;
     let border_y = (high_row as f32 - 2.0) * f32::TILE_SIZE;
 
-    let has_low_vert_border = has_border(map, low_row, low_col, low_row, high_col);
-    let has_high_vert_border = has_border(map, high_row, low_col, high_row, high_col);
+    let has_low_vert_border = has_border(map, terrain, low_row, low_col, low_row, high_col);
+    let has_high_vert_border = has_border(map, terrain, high_row, low_col, high_row, high_col);
 
-    let has_low_horiz_border = has_border(map, low_row, low_col, high_row, low_col);
-    let has_high_horiz_border = has_border(map, low_row, high_col, high_row, high_col);
+    let has_low_horiz_border = has_border(map, terrain, low_row, low_col, high_row, low_col);
+    let has_high_horiz_border = has_border(map, terrain, low_row, high_col, high_row, high_col);
 
     // We might hit vertical and horizontal borders on the same tick.
     // In this case, we should handle the smaller overlap first.
@@ -1,23 +1,361 @@
 use serde::{Deserialize, Serialize};
 
-use crate::waves::Wave;
+use crate::{health::DamageKind, waves::Wave};
 
 #[derive(Serialize, Deserialize, Default)]
 pub struct Config {
     pub common: Vec<TowerType>,
+    #[serde(default)]
+    pub enemies: Vec<EnemyType>,
     pub waves: Vec<Wave>,
+    /// Enemies that can reach the exit before the game is lost.
+    #[serde(default = "default_lives")]
+    pub lives: u32,
+    #[serde(default)]
+    pub timing: Timing,
+    #[serde(default)]
+    pub timeline: TimelineConfig,
+    /// Generate waves once the authored waves run out.
+    #[serde(default)]
+    pub endless: Option<Endless>,
+    #[serde(default)]
+    pub swallow: SwallowConfig,
+    #[serde(default)]
+    pub missile: MissileConfig,
+    #[serde(default)]
+    pub hazard: HazardConfig,
+    #[serde(default)]
+    pub factory: FactoryConfig,
+    #[serde(default)]
+    pub terrain: TerrainConfig,
+    /// Bonuses that towers get from adjacent towers.
+    #[serde(default)]
+    pub synergies: Vec<Synergy>,
+    /// Difficulties that a game can be started on.
+    #[serde(default)]
+    pub difficulties: Vec<Difficulty>,
+}
+
+/// Pacing for the level's waves. Waves can override some of these.
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct Timing {
+    /// Seconds before the first wave.
+    pub first_wave: f32,
+    /// Seconds from the start of one wave to the start of the next.
+    pub wave_interval: f32,
+    /// Seconds between spawns from the same entrance, for groups that don't
+    /// set their own spacing.
+    pub spawn_spacing: f32,
+    /// Hold the countdown to each wave until the field is clear of enemies.
+    pub wait_for_clear: bool,
+    /// Seconds of bonus build time granted for every second skipped by
+    /// sending a wave early.
+    pub early_call_bonus: f32,
+}
+
+impl Default for Timing {
+    fn default() -> Self {
+        Timing {
+            first_wave: 3.0,
+            wave_interval: 20.0,
+            spawn_spacing: 0.35,
+            wait_for_clear: false,
+            early_call_bonus: 0.0,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct TimelineConfig {
+    /// Megabytes of snapshots to keep before forgetting old checkpoints.
+    pub memory_budget: f32,
+}
+
+impl Default for TimelineConfig {
+    fn default() -> Self {
+        TimelineConfig {
+            memory_budget: 32.0,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct SwallowConfig {
+    /// Swallows per tower, before upgrades.
+    pub count: u32,
+    /// Swallows flock with swallows within this many tiles, if their towers
+    /// are the same or adjacent.
+    pub flock_radius: f32,
+    /// How strongly swallows steer away from their flockmates.
+    pub separation: f32,
+    /// How strongly swallows steer to match their flockmates' headings.
+    pub alignment: f32,
+}
+
+impl Default for SwallowConfig {
+    fn default() -> Self {
+        SwallowConfig {
+            count: 1,
+            flock_radius: 1.5,
+            separation: 0.6,
+            alignment: 0.2,
+        }
+    }
+}
+
+/// Flight and warhead settings for missiles and missile towers. Distances are
+/// in tiles, speeds in pixels per tick, and turning in radians per tick.
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct MissileConfig {
+    pub max_speed: f32,
+    pub acceleration: f32,
+    pub max_turn_speed: f32,
+    pub rotation_accel: f32,
+    pub tower_max_turn_speed: f32,
+    pub tower_rotation_accel: f32,
+    pub explosion_radius: f32,
+    /// Launchers on a tower before upgrades. The tower fires from one
+    /// launcher at a time.
+    pub launchers: u32,
+    /// Sub-explosions scattered by a cluster warhead.
+    pub cluster_count: u32,
+    /// Distance from the main explosion to each sub-explosion.
+    pub cluster_spread: f32,
+    pub cluster_radius: f32,
+    /// Fraction of the missile's damage dealt by each sub-explosion.
+    pub cluster_damage: f32,
+}
+
+impl Default for MissileConfig {
+    fn default() -> Self {
+        MissileConfig {
+            max_speed: 5.0,
+            acceleration: 0.31,
+            max_turn_speed: 0.13,
+            rotation_accel: 0.05,
+            tower_max_turn_speed: 0.08,
+            tower_rotation_accel: 0.002,
+            explosion_radius: 1.2,
+            launchers: 2,
+            cluster_count: 4,
+            cluster_spread: 0.8,
+            cluster_radius: 0.6,
+            cluster_damage: 0.35,
+        }
+    }
+}
+
+/// Fire and gas that spreads along the path. Density is unitless; a density
+/// of 1 or more has the full effect.
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct HazardConfig {
+    /// How quickly density spreads to neighboring cells.
+    pub diffusion: f32,
+    /// Fraction of density lost every tick.
+    pub decay: f32,
+    /// Relaxation iterations per tick. More is more accurate but slower.
+    pub iterations: u32,
+    /// Density added to each path cell near a fire tower every tick.
+    pub fire_emission: f32,
+    /// Damage per tick at full density.
+    pub damage: f32,
+    /// Densities at or below this deal no damage.
+    pub threshold: f32,
+    /// Fraction of speed lost at full density.
+    pub slow: f32,
+}
+
+impl Default for HazardConfig {
+    fn default() -> Self {
+        HazardConfig {
+            diffusion: 0.2,
+            decay: 0.02,
+            iterations: 4,
+            fire_emission: 0.05,
+            damage: 0.5,
+            threshold: 0.05,
+            slow: 0.4,
+        }
+    }
+}
+
+/// Tuning for the factory modes.
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct FactoryConfig {
+    /// Build progress per tick that a factory adds to an order.
+    pub build_rate: f32,
+    /// Seconds of bonus build time that a factory in income mode adds to the
+    /// stockpile at the start of every wave.
+    pub income: f32,
+}
+
+impl Default for FactoryConfig {
+    fn default() -> Self {
+        FactoryConfig {
+            build_rate: 0.5,
+            income: 4.0,
+        }
+    }
+}
+
+/// Effects of the terrain that towers are built on and enemies walk over.
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct TerrainConfig {
+    /// Range bonus for towers on high ground, as a fraction of base range.
+    pub high_ground_range: f32,
+    /// How many times faster trees grow on rich soil. Trees can't be built
+    /// yet, so this does nothing until they can.
+    pub rich_soil_growth: f32,
+    /// Fraction of their speed that enemies keep while walking through mud.
+    pub mud_speed: f32,
+}
+
+impl Default for TerrainConfig {
+    fn default() -> Self {
+        TerrainConfig {
+            high_ground_range: 0.25,
+            rich_soil_growth: 1.5,
+            mud_speed: 0.6,
+        }
+    }
+}
+
+/// Settings for generating waves in endless mode.
+#[derive(Serialize, Deserialize)]
+pub struct Endless {
+    /// Seed for new games. Saves keep their own seed.
+    #[serde(default)]
+    pub seed: u64,
+    /// Total cost of the enemies in the first generated wave.
+    pub starting_budget: f32,
+    /// The budget is multiplied by this after every generated wave.
+    pub budget_growth: f32,
+    /// Every nth generated wave includes a boss. 0 means no bosses.
+    #[serde(default)]
+    pub boss_interval: u32,
+    /// Most groups in a generated wave, not counting the boss.
+    #[serde(default = "default_max_groups")]
+    pub max_groups: u32,
+}
+
+/// Multipliers applied on top of the rest of the config. The difficulty that a
+/// game is started on is kept in the core state, so saves and replays always
+/// play out on the difficulty they were recorded on.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Difficulty {
+    pub name: String,
+    pub enemy_health: f32,
+    pub enemy_speed: f32,
+    pub bounty: f32,
+    pub lives: f32,
+    /// How fast towers and upgrades are built.
+    pub build_speed: f32,
+}
+
+impl Default for Difficulty {
+    fn default() -> Self {
+        Difficulty {
+            name: "Normal".to_owned(),
+            enemy_health: 1.0,
+            enemy_speed: 1.0,
+            bounty: 1.0,
+            lives: 1.0,
+            build_speed: 1.0,
+        }
+    }
+}
+
+fn default_lives() -> u32 {
+    20
+}
+
+fn default_max_groups() -> u32 {
+    3
 }
 
 #[derive(Serialize, Deserialize, Default)]
 pub struct TowerType {
     pub name: String,
     pub base_damage: f32,
+    /// Attacks per second. Swallows attack this often against an enemy three
+    /// tiles away, and faster when enemies are closer.
     pub base_rate_of_fire: f32,
     pub base_range: f32,
     pub cost: f32,
     pub description: String,
     pub flavor: String,
     pub color: u32,
+    /// At most 8, since built upgrades are stored as bit flags.
+    #[serde(default)]
+    pub upgrades: Vec<UpgradeType>,
+    /// Whether the tower can be built on water.
+    #[serde(default)]
+    pub builds_on_water: bool,
+}
+
+/// An upgrade that can be built on a tower once the tower is done.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct UpgradeType {
+    pub name: String,
+    pub cost: f32,
+    #[serde(default)]
+    pub description: String,
+    #[serde(flatten)]
+    pub effect: UpgradeEffect,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(tag = "effect")]
+pub enum UpgradeEffect {
+    /// Adds swallows to a swallow tower.
+    Swallows { count: u32 },
+    /// Missiles scatter sub-explosions when they hit.
+    ClusterWarhead,
+    /// Missiles pick targets that no other missile is locked onto.
+    Homing,
+    /// Adds launchers to a missile tower, each of which adds a missile to every
+    /// salvo.
+    Salvo { launchers: u32 },
+}
+
+/// A bonus that a tower gets for each orthogonally adjacent tower of a
+/// certain type.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Synergy {
+    /// Name of the tower type that gets the bonus. Missing means any tower.
+    #[serde(default)]
+    pub tower: Option<String>,
+    /// Name of the tower type that gives the bonus.
+    pub neighbor: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(flatten)]
+    pub effect: SynergyEffect,
+}
+
+/// Bonuses are fractions of the base stat, and add up over neighbors and
+/// synergies.
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(tag = "effect")]
+pub enum SynergyEffect {
+    Range {
+        bonus: f32,
+    },
+    RateOfFire {
+        bonus: f32,
+    },
+    /// Increases the hazard emitted by fire towers.
+    Burning {
+        bonus: f32,
+    },
 }
 
 // We often need a default value for TowerType because we prefer unwrap_or over
@@ -35,10 +373,151 @@ pub const DEFAULT_TOWER_TYPE: &TowerType = &TowerType {
     description: String::new(),
     flavor: String::new(),
     color: 0,
+    upgrades: Vec::new(),
+    builds_on_water: false,
+};
+
+#[derive(Serialize, Deserialize)]
+pub struct EnemyType {
+    pub name: String,
+    pub health: f32,
+    pub speed: f32,
+    #[serde(default = "default_enemy_color")]
+    pub color: u32,
+    /// Difficulty cost for endless mode. Enemies without a cost are never
+    /// picked for generated waves.
+    #[serde(default)]
+    pub cost: f32,
+    /// Reward for killing this enemy, reported with its EnemyKilled event.
+    #[serde(default)]
+    pub bounty: f32,
+    /// Name of the enemy type that this enemy splits into when it dies.
+    #[serde(default)]
+    pub splits_into: Option<String>,
+    #[serde(default)]
+    pub split_count: u32,
+    /// Periodically heals nearby walkers.
+    #[serde(default)]
+    pub heal: Option<Aura>,
+    /// Periodically shields nearby walkers.
+    #[serde(default)]
+    pub shield: Option<Aura>,
+    /// Hazard density emitted every tick. Toxic enemies are immune to the
+    /// hazard.
+    #[serde(default)]
+    pub toxic: f32,
+    /// Bosses get a health bar in the UI and can have phases.
+    #[serde(default)]
+    pub boss: bool,
+    /// Boss phases, ordered by descending health threshold.
+    #[serde(default)]
+    pub phases: Vec<Phase>,
+}
+
+/// A change in a boss's behavior that happens when its health gets low enough.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Phase {
+    /// The phase begins when health drops to this fraction of max health.
+    pub health_threshold: f32,
+    #[serde(default)]
+    pub speed_multiplier: Option<f32>,
+    /// Name of the enemy type to spawn around the boss.
+    #[serde(default)]
+    pub minions: Option<String>,
+    #[serde(default)]
+    pub minion_count: u32,
+    /// Replaces the boss's immunities from previous phases.
+    #[serde(default)]
+    pub immune_to: Option<Vec<DamageKind>>,
+    /// Replaces the boss's shield. A shield of 0 drops the current shield.
+    #[serde(default)]
+    pub shield: Option<f32>,
+}
+
+/// An effect that an enemy periodically applies to the walkers around it.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Aura {
+    /// Measured in tiles.
+    pub radius: f32,
+    /// Health restored or shield granted to each walker in range.
+    pub amount: f32,
+    /// Ticks between applications.
+    pub period: u32,
+    /// Ticks that a granted shield lasts. Unused by heal auras.
+    #[serde(default)]
+    pub duration: u32,
+}
+
+fn default_enemy_color() -> u32 {
+    DEFAULT_ENEMY_TYPE.color
+}
+
+/// Fallback for waves that reference an enemy type missing from the config.
+/// Matches the stats that every enemy had before enemy types were
+/// configurable.
+pub const DEFAULT_ENEMY_TYPE: &EnemyType = &EnemyType {
+    name: String::new(),
+    health: 100.0,
+    speed: 1.5,
+    color: 0x777777,
+    cost: 0.0,
+    bounty: 0.0,
+    splits_into: None,
+    split_count: 0,
+    heal: None,
+    shield: None,
+    toxic: 0.0,
+    boss: false,
+    phases: Vec::new(),
 };
 
 impl Config {
     pub fn get_common(&self, i: usize) -> &TowerType {
         self.common.get(i).unwrap_or(DEFAULT_TOWER_TYPE)
     }
+
+    pub fn get_enemy(&self, i: usize) -> &EnemyType {
+        self.enemies.get(i).unwrap_or(DEFAULT_ENEMY_TYPE)
+    }
+
+    /// Seconds before the wave with the given index, counted from the start of
+    /// the previous wave (or the start of the level).
+    pub fn wave_interval(&self, index: usize) -> f32 {
+        match self.waves.get(index).and_then(|wave| wave.interval) {
+            Some(interval) => interval,
+            None if index == 0 => self.timing.first_wave,
+            None => self.timing.wave_interval,
+        }
+    }
+
+    pub fn wave_waits_for_clear(&self, index: usize) -> bool {
+        self.waves
+            .get(index)
+            .and_then(|wave| wave.wait_for_clear)
+            .unwrap_or(self.timing.wait_for_clear)
+    }
+
+    /// Find a tower type by name.
+    pub fn tower_index(&self, name: &str) -> Option<usize> {
+        self.common.iter().position(|tower| tower.name == name)
+    }
+
+    /// Find a difficulty by name. Unknown names get the default difficulty,
+    /// which doesn't change anything.
+    pub fn difficulty(&self, name: &str) -> Difficulty {
+        self.difficulties
+            .iter()
+            .find(|difficulty| difficulty.name == name)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Find an enemy type by name. Unknown names map to an out of bounds
+    /// index, which get_enemy resolves to the default enemy type.
+    pub fn enemy_index(&self, name: &str) -> usize {
+        self.enemies
+            .iter()
+            .position(|enemy| enemy.name == name)
+            .unwrap_or(self.enemies.len())
+    }
 }
@@ -0,0 +1,449 @@
+//! Editing the level's map in game. The map is edited as glyphs, like the
+//! textual representation in map.rs, except that corners are worked out from
+//! the way the path flows into each tile instead of being drawn by hand.
+//!
+//! Every edit reparses the map and starts the level over, so that designers
+//! can playtest a change right away. Edits aren't logged commands, since a
+//! timeline only ever covers a single map.
+
+use wasm_bindgen::prelude::*;
+
+use crate::{
+    graphics::clear_map,
+    map::{
+        distances::{generate_dist_from_entrance, generate_dist_from_exit},
+        entrances,
+        generate::{generate, MapSettings},
+        glyph_terrain, parse, parse_terrain, path_glyph, render_map, Terrain, Tile, MAP_HEIGHT,
+        MAP_WIDTH, TRUE_MAP_HEIGHT, TRUE_MAP_WIDTH,
+    },
+    world::{start_level, RunState, World},
+};
+
+/// A direction as (d_row, d_col).
+type Heading = (isize, isize);
+
+const NORTH: Heading = (-1, 0);
+const SOUTH: Heading = (1, 0);
+const EAST: Heading = (0, 1);
+const WEST: Heading = (0, -1);
+
+/// Glyphs that can be painted. Corners are worked out automatically, so x
+/// isn't one of them.
+const PAINTABLE_GLYPHS: &str = "# *^%~nv><NSEW";
+
+fn heading(glyph: char) -> Option<Heading> {
+    match path_glyph(glyph) {
+        'n' => Some(NORTH),
+        'v' => Some(SOUTH),
+        '>' => Some(EAST),
+        '<' => Some(WEST),
+        _ => None,
+    }
+}
+
+fn heading_glyph(heading: Heading, mud: bool) -> char {
+    let glyphs = if mud {
+        ['N', 'S', 'E', 'W']
+    } else {
+        ['n', 'v', '>', '<']
+    };
+    match heading {
+        NORTH => glyphs[0],
+        SOUTH => glyphs[1],
+        EAST => glyphs[2],
+        _ => glyphs[3],
+    }
+}
+
+fn is_mud(glyph: char) -> bool {
+    glyph_terrain(glyph) == Terrain::Mud
+}
+
+/// The way a path tile leads, after turning if it's a corner.
+fn tile_heading(tile: Tile) -> Option<Heading> {
+    match tile {
+        Tile::North | Tile::EastToNorth | Tile::WestToNorth => Some(NORTH),
+        Tile::South | Tile::EastToSouth | Tile::WestToSouth => Some(SOUTH),
+        Tile::East | Tile::NorthToEast | Tile::SouthToEast => Some(EAST),
+        Tile::West | Tile::NorthToWest | Tile::SouthToWest => Some(WEST),
+        Tile::Empty | Tile::OutOfBounds => None,
+    }
+}
+
+fn path_tile(facing: Heading, turning: Heading) -> Tile {
+    match (facing, turning) {
+        (NORTH, EAST) => Tile::NorthToEast,
+        (NORTH, WEST) => Tile::NorthToWest,
+        (SOUTH, EAST) => Tile::SouthToEast,
+        (SOUTH, WEST) => Tile::SouthToWest,
+        (EAST, NORTH) => Tile::EastToNorth,
+        (EAST, SOUTH) => Tile::EastToSouth,
+        (WEST, NORTH) => Tile::WestToNorth,
+        (WEST, SOUTH) => Tile::WestToSouth,
+        (_, NORTH) => Tile::North,
+        (_, SOUTH) => Tile::South,
+        (_, EAST) => Tile::East,
+        _ => Tile::West,
+    }
+}
+
+fn is_corner(tile: Tile) -> bool {
+    match tile_heading(tile) {
+        Some(heading) => tile != path_tile(heading, heading),
+        None => false,
+    }
+}
+
+fn glyph_at(glyphs: &[char], true_row: isize, true_col: isize) -> Option<char> {
+    if true_row < 0
+        || true_col < 0
+        || true_row >= TRUE_MAP_HEIGHT as isize
+        || true_col >= TRUE_MAP_WIDTH as isize
+    {
+        return None;
+    }
+    glyphs
+        .get(true_row as usize * TRUE_MAP_WIDTH + true_col as usize)
+        .copied()
+}
+
+/// A tile's position for error messages, in visible coordinates.
+fn describe(index: usize) -> String {
+    format!(
+        "row {}, col {}",
+        (index / TRUE_MAP_WIDTH) as isize - 2,
+        (index % TRUE_MAP_WIDTH) as isize - 2
+    )
+}
+
+/// Turn the textual representation of a map into glyphs that the editor can
+/// work with, by replacing corners with the way they turn.
+pub fn uncorner(map_str: &[&str]) -> Vec<char> {
+    map_str
+        .iter()
+        .flat_map(|row_str| row_str.chars())
+        .zip(parse(map_str))
+        .map(|(glyph, tile)| match (glyph, tile_heading(tile)) {
+            ('x', Some(heading)) => heading_glyph(heading, false),
+            _ => glyph,
+        })
+        .collect()
+}
+
+/// The way the path flows into a tile, found by looking for a neighbor that
+/// leads into it. Going straight wins over turning.
+fn facing(glyphs: &[char], true_row: usize, true_col: usize, turning: Heading) -> Heading {
+    let candidates = [turning, (turning.1, turning.0), (-turning.1, -turning.0)];
+    for &candidate in &candidates {
+        let behind = glyph_at(
+            glyphs,
+            true_row as isize - candidate.0,
+            true_col as isize - candidate.1,
+        );
+        if behind.and_then(heading) == Some(candidate) {
+            return candidate;
+        }
+    }
+    turning
+}
+
+/// Work out the tiles of a map from its glyphs, including which way corners
+/// turn.
+pub fn tiles(glyphs: &[char]) -> Vec<Tile> {
+    glyphs
+        .iter()
+        .enumerate()
+        .map(|(i, &glyph)| {
+            let (true_row, true_col) = (i / TRUE_MAP_WIDTH, i % TRUE_MAP_WIDTH);
+            match heading(glyph) {
+                Some(turning) => path_tile(facing(glyphs, true_row, true_col, turning), turning),
+                None if " *^%~".contains(glyph) => Tile::Empty,
+                None => Tile::OutOfBounds,
+            }
+        })
+        .collect()
+}
+
+/// The map in its textual representation, one row per line.
+pub fn export(glyphs: &[char]) -> String {
+    let mut text = String::with_capacity(glyphs.len() + TRUE_MAP_HEIGHT);
+    for (i, (&glyph, tile)) in glyphs.iter().zip(tiles(glyphs)).enumerate() {
+        if i > 0 && i % TRUE_MAP_WIDTH == 0 {
+            text.push('\n');
+        }
+        text.push(if is_corner(tile) { 'x' } else { glyph });
+    }
+    text
+}
+
+/// Follow the path from a tile until it leaves the map through an exit.
+fn follow_path(map: &[Tile], start: usize) -> Result<(), String> {
+    let mut index = start;
+    for _ in 0..map.len() {
+        let heading = match tile_heading(map[index]) {
+            Some(heading) => heading,
+            None => return Err(format!("The path at {} leads nowhere", describe(start))),
+        };
+        let next_row = (index / TRUE_MAP_WIDTH) as isize + heading.0;
+        let next_col = (index % TRUE_MAP_WIDTH) as isize + heading.1;
+        // Only exits lead onto the outermost ring of padding
+        if next_row <= 0
+            || next_col <= 0
+            || next_row >= TRUE_MAP_HEIGHT as isize - 1
+            || next_col >= TRUE_MAP_WIDTH as isize - 1
+        {
+            return Ok(());
+        }
+        index = next_row as usize * TRUE_MAP_WIDTH + next_col as usize;
+    }
+    Err(format!(
+        "The path at {} goes around in circles",
+        describe(start)
+    ))
+}
+
+/// Find a problem that would stop the map from being played or written as
+/// text, if there is one.
+pub fn validate(glyphs: &[char]) -> Result<(), String> {
+    let map = tiles(glyphs);
+    if entrances(&map).is_empty() {
+        return Err("The path needs an entrance".to_owned());
+    }
+    for (i, &tile) in map.iter().enumerate() {
+        if tile_heading(tile).is_some() {
+            follow_path(&map, i)?;
+        }
+    }
+
+    // Corners are written as x, and parsing works out which way they turn from
+    // their neighbors. That doesn't work for every arrangement of corners, and
+    // corners can't be muddy.
+    let text = export(glyphs);
+    let rows: Vec<&str> = text.lines().collect();
+    let parsed = parse(&rows);
+    let terrain = parse_terrain(&rows);
+    for (i, &glyph) in glyphs.iter().enumerate() {
+        if parsed[i] != map[i] || terrain[i] != glyph_terrain(glyph) {
+            return Err(format!(
+                "The corner at {} can't be written as text",
+                describe(i)
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Tiles just outside the visible map next to an edge tile, and the way into
+/// the map from them.
+fn outside_tiles(row: usize, col: usize) -> Vec<((usize, usize), Heading)> {
+    let (true_row, true_col) = (row + 2, col + 2);
+    let mut outside = Vec::new();
+    if row >= MAP_HEIGHT || col >= MAP_WIDTH {
+        return outside;
+    }
+    if col == 0 {
+        outside.push(((true_row, 1), EAST));
+    }
+    if col == MAP_WIDTH - 1 {
+        outside.push(((true_row, MAP_WIDTH + 2), WEST));
+    }
+    if row == 0 {
+        outside.push(((1, true_col), SOUTH));
+    }
+    if row == MAP_HEIGHT - 1 {
+        outside.push(((MAP_HEIGHT + 2, true_col), NORTH));
+    }
+    outside
+}
+
+impl World {
+    fn glyph(&self, true_row: usize, true_col: usize) -> char {
+        self.level_state.glyphs[true_row * TRUE_MAP_WIDTH + true_col]
+    }
+
+    fn set_glyph(&mut self, true_row: usize, true_col: usize, glyph: char) {
+        self.level_state.glyphs[true_row * TRUE_MAP_WIDTH + true_col] = glyph;
+    }
+
+    /// Reparse the map after an edit and start the level over on it.
+    fn reload_level(&mut self) {
+        let glyphs = &self.level_state.glyphs;
+        let map = tiles(glyphs);
+        let terrain: Vec<Terrain> = glyphs.iter().copied().map(glyph_terrain).collect();
+
+        clear_map();
+        render_map(&map, &terrain);
+
+        let difficulty = self.core_state.difficulty.clone();
+        let (core_state, timeline) = start_level(&map, &self.config, difficulty);
+        self.core_state = core_state;
+        self.timeline = timeline;
+        self.run_state = RunState::AutoPaused;
+        self.render_state.blueprint.clear();
+        self.render_state.build_etas = None;
+
+        self.level_state.dist_from_entrance = generate_dist_from_entrance(&map);
+        self.level_state.dist_from_exit = generate_dist_from_exit(&map);
+        self.level_state.map = map;
+        self.level_state.terrain = terrain;
+    }
+}
+
+#[wasm_bindgen]
+impl World {
+    /// Paint a glyph from the textual representation of maps onto a tile.
+    /// Returns false if the glyph can't be painted there.
+    pub fn paint_map_tile(&mut self, row: usize, col: usize, glyph: char) -> bool {
+        if row >= MAP_HEIGHT || col >= MAP_WIDTH || !PAINTABLE_GLYPHS.contains(glyph) {
+            return false;
+        }
+        self.set_glyph(row + 2, col + 2, glyph);
+        // Entrances and exits need the path on the edge of the map to connect
+        if heading(glyph).is_none() {
+            for ((outside_row, outside_col), _) in outside_tiles(row, col) {
+                self.set_glyph(outside_row, outside_col, '#');
+            }
+        }
+        self.reload_level();
+        true
+    }
+
+    /// Start drawing the path from a tile, like when the player presses the
+    /// mouse button.
+    pub fn start_map_path(&mut self, row: usize, col: usize) {
+        if row < MAP_HEIGHT && col < MAP_WIDTH {
+            self.render_state.editor_path_end = Some((row + 2, col + 2));
+        }
+    }
+
+    /// Draw the path on to a tile, like when the player drags the mouse. Each
+    /// tile on the way is pointed at the next one. Tiles that aren't next to
+    /// the end of the path are reached by going along the longer axis first.
+    pub fn extend_map_path(&mut self, row: usize, col: usize) {
+        let mut end = match self.render_state.editor_path_end {
+            Some(end) => end,
+            None => return,
+        };
+        if row >= MAP_HEIGHT || col >= MAP_WIDTH {
+            return;
+        }
+        let target = (row + 2, col + 2);
+        if end == target {
+            return;
+        }
+        while end != target {
+            let d_row = target.0 as isize - end.0 as isize;
+            let d_col = target.1 as isize - end.1 as isize;
+            let step = if d_row.abs() >= d_col.abs() {
+                (d_row.signum(), 0)
+            } else {
+                (0, d_col.signum())
+            };
+            let glyph = heading_glyph(step, is_mud(self.glyph(end.0, end.1)));
+            self.set_glyph(end.0, end.1, glyph);
+            end = (
+                (end.0 as isize + step.0) as usize,
+                (end.1 as isize + step.1) as usize,
+            );
+            // Keep going straight unless we joined up with the existing path
+            if heading(self.glyph(end.0, end.1)).is_none() {
+                self.set_glyph(end.0, end.1, heading_glyph(step, false));
+            }
+        }
+        self.render_state.editor_path_end = Some(end);
+        self.reload_level();
+    }
+
+    /// Make enemies enter the map through a tile on its edge, and continue
+    /// drawing the path from there. Returns false if the tile isn't on the
+    /// edge.
+    pub fn place_map_entrance(&mut self, row: usize, col: usize) -> bool {
+        let ((outside_row, outside_col), inward) = match outside_tiles(row, col).first() {
+            Some(&outside) => outside,
+            None => return false,
+        };
+        let (true_row, true_col) = (row + 2, col + 2);
+        self.set_glyph(outside_row, outside_col, heading_glyph(inward, false));
+        if heading(self.glyph(true_row, true_col)).is_none() {
+            self.set_glyph(true_row, true_col, heading_glyph(inward, false));
+        }
+        self.render_state.editor_path_end = Some((true_row, true_col));
+        self.reload_level();
+        true
+    }
+
+    /// Make enemies leave the map through a tile on its edge. Returns false if
+    /// the tile isn't on the edge.
+    pub fn place_map_exit(&mut self, row: usize, col: usize) -> bool {
+        let ((outside_row, outside_col), inward) = match outside_tiles(row, col).first() {
+            Some(&outside) => outside,
+            None => return false,
+        };
+        let outward = (-inward.0, -inward.1);
+        let (true_row, true_col) = (row + 2, col + 2);
+        let mud = is_mud(self.glyph(true_row, true_col));
+        self.set_glyph(true_row, true_col, heading_glyph(outward, mud));
+        self.set_glyph(outside_row, outside_col, heading_glyph(outward, false));
+        self.render_state.editor_path_end = None;
+        self.reload_level();
+        true
+    }
+
+    /// Replace the map with a generated one. Returns false, leaving the map
+    /// as it was, if no map turned up for these settings.
+    pub fn generate_map(&mut self, seed: u32, paths: usize, length: usize, turns: usize) -> bool {
+        let settings = MapSettings {
+            paths,
+            length,
+            turns,
+        };
+        match generate(seed as u64, &settings) {
+            Some(glyphs) => {
+                self.level_state.glyphs = glyphs;
+                self.render_state.editor_path_end = None;
+                self.reload_level();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Describe what's wrong with the map, or return an empty string if
+    /// nothing is.
+    pub fn query_map_problem(&self) -> String {
+        validate(&self.level_state.glyphs).err().unwrap_or_default()
+    }
+
+    /// The map in its textual representation, like MAP_0.
+    pub fn export_map(&self) -> String {
+        export(&self.level_state.glyphs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::map::MAP_0;
+
+    #[test]
+    fn corners_are_worked_out_like_the_parser_does() {
+        let glyphs = uncorner(&MAP_0);
+        assert!(!glyphs.contains(&'x'));
+        assert!(tiles(&glyphs) == parse(&MAP_0));
+        assert_eq!(export(&glyphs), MAP_0.join("\n"));
+        assert_eq!(validate(&glyphs), Ok(()));
+    }
+
+    #[test]
+    fn paths_must_reach_an_exit() {
+        let mut glyphs = uncorner(&MAP_0);
+        // Break the path in row 3, which runs east from the entrance
+        glyphs[3 * TRUE_MAP_WIDTH + 3] = ' ';
+        assert_eq!(
+            validate(&glyphs),
+            Err("The path at row 1, col -1 leads nowhere".to_owned())
+        );
+    }
+}
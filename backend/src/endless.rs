@@ -0,0 +1,151 @@
+//! Wave generation for endless mode.
+
+use float_ord::FloatOrd;
+
+use crate::{
+    config::{Config, Endless},
+    rng::Rng,
+    waves::{Group, Wave},
+};
+
+/// Keeps runaway budgets from queueing absurd numbers of enemies.
+const MAX_GROUP_SIZE: u32 = 100;
+
+/// Generate the nth wave after the authored waves. The same seed and n always
+/// generate the same wave.
+pub fn generate_wave(config: &Config, endless: &Endless, seed: u64, n: usize) -> Wave {
+    // Mix n into the seed so that every wave gets its own sequence
+    let mut rng = Rng::new(seed ^ (n as u64).wrapping_mul(0x2545_f491_4f6c_dd1d));
+    let mut budget = endless.starting_budget * endless.budget_growth.powi(n as i32);
+    let mut groups = Vec::new();
+
+    let boss_wave = endless.boss_interval > 0 && (n + 1) % endless.boss_interval as usize == 0;
+    let boss = if boss_wave {
+        pick_enemy(config, &mut rng, budget, true)
+    } else {
+        None
+    };
+    if let Some(boss) = boss {
+        budget -= config.get_enemy(boss).cost;
+    }
+
+    let num_groups = 1 + rng.below(endless.max_groups.max(1) as usize);
+    let share = budget / num_groups as f32;
+    for _ in 0..num_groups {
+        if let Some(enemy_type) = pick_enemy(config, &mut rng, share, false) {
+            let enemy = config.get_enemy(enemy_type);
+            let size = ((share / enemy.cost) as u32).clamp(1, MAX_GROUP_SIZE);
+            groups.push(Group::new(size, enemy.name.clone()));
+        }
+    }
+
+    // Bosses come last, after their escorts
+    if let Some(boss) = boss {
+        groups.push(Group::new(1, config.get_enemy(boss).name.clone()));
+    }
+
+    Wave {
+        group: groups,
+        ..Default::default()
+    }
+}
+
+/// Pick a random enemy type that fits in the budget, or the cheapest one if
+/// none fit.
+fn pick_enemy(config: &Config, rng: &mut Rng, budget: f32, boss: bool) -> Option<usize> {
+    let candidates: Vec<usize> = (0..config.enemies.len())
+        .filter(|&i| config.enemies[i].cost > 0.0 && config.enemies[i].boss == boss)
+        .collect();
+    let affordable: Vec<usize> = candidates
+        .iter()
+        .copied()
+        .filter(|&i| config.enemies[i].cost <= budget)
+        .collect();
+    if affordable.is_empty() {
+        candidates
+            .into_iter()
+            .min_by_key(|&i| FloatOrd(config.enemies[i].cost))
+    } else {
+        Some(affordable[rng.below(affordable.len())])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> Config {
+        toml::from_str(
+            r#"
+            common = []
+            waves = []
+
+            [endless]
+            starting_budget = 10.0
+            budget_growth = 1.5
+            boss_interval = 2
+
+            [[enemies]]
+            name = "Cheap"
+            health = 100.0
+            speed = 1.5
+            cost = 1.0
+
+            [[enemies]]
+            name = "Pricey"
+            health = 400.0
+            speed = 1.0
+            cost = 4.0
+
+            [[enemies]]
+            name = "Boss"
+            health = 2000.0
+            speed = 0.8
+            cost = 8.0
+            boss = true
+            "#,
+        )
+        .unwrap()
+    }
+
+    fn describe(wave: &Wave) -> Vec<(u32, String)> {
+        wave.group
+            .iter()
+            .map(|group| (group.size, group.r#type.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn same_seed_same_waves() {
+        // Pinned so that changes to generation, which would change the waves
+        // of saved games, don't go unnoticed
+        let config = config();
+        let endless = config.endless.as_ref().unwrap();
+        let expected: [&[(u32, &str)]; 6] = [
+            &[(10, "Cheap")],
+            &[(7, "Cheap"), (1, "Boss")],
+            &[(7, "Cheap"), (7, "Cheap"), (7, "Cheap")],
+            &[(2, "Pricey"), (8, "Cheap"), (2, "Pricey"), (1, "Boss")],
+            &[(50, "Cheap")],
+            &[(33, "Cheap"), (33, "Cheap"), (1, "Boss")],
+        ];
+        for (n, groups) in expected.iter().enumerate() {
+            let groups: Vec<_> = groups
+                .iter()
+                .map(|&(size, name)| (size, name.to_owned()))
+                .collect();
+            assert_eq!(describe(&generate_wave(&config, endless, 7, n)), groups);
+        }
+    }
+
+    #[test]
+    fn bosses_come_at_intervals() {
+        let config = config();
+        let endless = config.endless.as_ref().unwrap();
+        for n in 0..10 {
+            let wave = generate_wave(&config, endless, 7, n);
+            let has_boss = wave.group.iter().any(|group| group.r#type == "Boss");
+            assert_eq!(has_boss, n % 2 == 1);
+        }
+    }
+}
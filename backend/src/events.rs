@@ -0,0 +1,112 @@
+//! Things that happened in the simulation that the frontend might want to
+//! react to, like playing a sound or showing a message.
+//!
+//! Events are queued as the systems run and drained by the client once per
+//! frame. They aren't part of the core state, so they aren't saved, and
+//! replaying to a checkpoint doesn't emit them again.
+
+use wasm_bindgen::prelude::*;
+
+use crate::world::World;
+
+#[wasm_bindgen]
+#[repr(u8)]
+#[derive(Clone, Copy)]
+pub enum EventType {
+    /// Value is the enemy type index.
+    EnemySpawned,
+    /// Value is the enemy's bounty.
+    EnemyKilled,
+    /// Value is the number of lives left.
+    EnemyLeaked,
+    /// Value is the tower type index.
+    TowerCompleted,
+    /// Value is the upgrade flag.
+    UpgradeCompleted,
+    /// Value is the wave index. Entity is 0.
+    WaveStarted,
+    /// Value is the wave index. Entity is 0.
+    WaveCleared,
+    /// The last life was lost. Value is the index of the wave that got
+    /// through. Entity is 0.
+    GameOver,
+}
+
+pub struct Event {
+    pub event_type: EventType,
+    pub entity: u32,
+    pub x: f32,
+    pub y: f32,
+    /// Meaning depends on the event type.
+    pub value: f32,
+}
+
+/// Drained events, stored so that they can be read from js.
+#[derive(Default)]
+pub struct EventData {
+    event_type: Vec<u8>,
+    entity: Vec<u32>,
+    x: Vec<f32>,
+    y: Vec<f32>,
+    value: Vec<f32>,
+}
+
+impl EventData {
+    fn clear(&mut self) {
+        self.event_type.clear();
+        self.entity.clear();
+        self.x.clear();
+        self.y.clear();
+        self.value.clear();
+    }
+
+    fn push(&mut self, event: &Event) {
+        self.event_type.push(event.event_type as u8);
+        self.entity.push(event.entity);
+        self.x.push(event.x);
+        self.y.push(event.y);
+        self.value.push(event.value);
+    }
+}
+
+impl World {
+    pub fn emit(&mut self, event: Event) {
+        if !self.timeline.is_replaying() {
+            self.events.push(event);
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl World {
+    /// Move queued events into the event buffers and return how many there
+    /// are.
+    pub fn drain_events(&mut self) -> usize {
+        let data = &mut self.render_state.event_data;
+        data.clear();
+        for event in self.events.drain(..) {
+            data.push(&event);
+        }
+        data.event_type.len()
+    }
+
+    pub fn event_type(&self) -> *const u8 {
+        self.render_state.event_data.event_type.as_ptr()
+    }
+
+    pub fn event_entity(&self) -> *const u32 {
+        self.render_state.event_data.entity.as_ptr()
+    }
+
+    pub fn event_x(&self) -> *const f32 {
+        self.render_state.event_data.x.as_ptr()
+    }
+
+    pub fn event_y(&self) -> *const f32 {
+        self.render_state.event_data.y.as_ptr()
+    }
+
+    pub fn event_value(&self) -> *const f32 {
+        self.render_state.event_data.value.as_ptr()
+    }
+}
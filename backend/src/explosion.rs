@@ -8,6 +8,7 @@ use crate::{
     collision::resolve_collisions,
     distance::fast_distance,
     graphics::{create_explosion, recycle_explosion, render_explosion},
+    health::{Damage, DamageKind},
     walker::{walk_direction, Velocity, STANDARD_ENEMY_RADIUS},
     world::{Map, World},
 };
@@ -27,6 +28,8 @@ pub struct Explosion {
     max_radius: f32,
     touched_entities: Vec<u32>,
     damage: f32,
+    /// Tower that caused the explosion.
+    source: Option<u32>,
 }
 
 pub fn spawn_explosion(
@@ -36,6 +39,7 @@ pub fn spawn_explosion(
     y: f32,
     max_radius: f32,
     damage: f32,
+    source: Option<u32>,
 ) {
     create_explosion(id, x, y);
 
@@ -50,6 +54,7 @@ pub fn spawn_explosion(
             max_radius,
             touched_entities: Vec::new(),
             damage,
+            source,
         },
     );
 }
@@ -100,13 +105,16 @@ impl World {
                                 * (1.5 - 0.5 * explosion.radius / explosion.max_radius);
 
                             // Deal damage
-                            if let Some(health) = self.core_state.health.get_mut(entity) {
-                                health.curr_health -= if full_damage {
+                            self.core_state.damage.push(Damage {
+                                target: *entity,
+                                amount: if full_damage {
                                     explosion.damage
                                 } else {
                                     explosion.damage / 2.0
-                                };
-                            }
+                                },
+                                kind: DamageKind::Explosive,
+                                source: explosion.source,
+                            });
                         }
                     }
                 }
@@ -154,6 +162,7 @@ impl World {
 
                     let impact = resolve_collisions(
                         &self.level_state.map,
+                        &self.level_state.terrain,
                         &mut mob.x,
                         &mut mob.y,
                         STANDARD_ENEMY_RADIUS,
@@ -1,14 +1,17 @@
 use std::collections::VecDeque;
 
 use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
 
 use crate::{
-    build::BuildOrder,
+    build::{BuildOrder, BuildType},
     config::Config,
     ease::ease_to_x_geometric,
     graphics::{RenderState, SpriteType},
     map::tile_center,
+    timeline::Command,
     tower::{create_tower, Tower, TowerStatus, FACTORY_INDEX},
+    waves::TICKS_PER_SECOND,
     world::{Map, World},
 };
 
@@ -17,6 +20,21 @@ pub struct Factory {
     pub rotation: f32,
     pub rotation_speed: f32,
     pub is_constructing: bool,
+    pub mode: FactoryMode,
+}
+
+/// What a factory does with its build power. Repairing nearby towers would be
+/// another mode, once towers can be damaged.
+#[wasm_bindgen]
+#[repr(u8)]
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum FactoryMode {
+    /// Help build adjacent towers and upgrades.
+    Construct,
+    /// Add bonus build time to the stockpile at the start of every wave.
+    Income,
+    /// Help build the first upgrade in the queue, wherever it is.
+    Upgrade,
 }
 
 const MAX_ROTATION_SPEED: f32 = 0.03;
@@ -59,12 +77,50 @@ pub fn create_factory(
             rotation: 0.0,
             rotation_speed: 0.0,
             is_constructing: false,
+            mode: FactoryMode::Construct,
         },
     );
 
     entity
 }
 
+/// Factories in upgrade mode put their build power into the first upgrade in
+/// the queue that can be built.
+pub fn build_upgrades(
+    build_queue: &mut VecDeque<BuildOrder>,
+    towers: &mut Map<u32, Tower>,
+    factories: &mut Map<u32, Factory>,
+    build_rate: f32,
+) {
+    let build_order = build_queue.iter_mut().find(|build_order| {
+        !build_order.paused
+            && build_order.progress < build_order.cost as f32
+            && matches!(
+                build_order.build_type,
+                BuildType::Upgrade {
+                    can_build: true,
+                    ..
+                }
+            )
+    });
+    let build_order = match build_order {
+        Some(build_order) => build_order,
+        None => return,
+    };
+    for (entity, factory) in factories {
+        if factory.mode != FactoryMode::Upgrade || factory.is_constructing {
+            continue;
+        }
+        if let Some(Tower { status, .. }) = towers.get(entity) {
+            if *status == TowerStatus::Operational {
+                factory.is_constructing = true;
+                build_order.notify_tower(towers);
+                build_order.progress += build_rate;
+            }
+        }
+    }
+}
+
 impl World {
     pub fn dump_factories(&mut self) {
         for (entity, factory) in &self.core_state.factories {
@@ -85,6 +141,21 @@ impl World {
         }
     }
 
+    /// Called when a wave starts.
+    pub fn collect_factory_income(&mut self) {
+        for (entity, factory) in &self.core_state.factories {
+            if factory.mode != FactoryMode::Income {
+                continue;
+            }
+            if let Some(Tower { status, .. }) = self.core_state.towers.get(entity) {
+                if *status == TowerStatus::Operational {
+                    self.core_state.stockpile +=
+                        self.config.factory.income * TICKS_PER_SECOND as f32;
+                }
+            }
+        }
+    }
+
     pub fn rotate_factories(&mut self) {
         for factory in self.core_state.factories.values_mut() {
             if factory.is_constructing {
@@ -113,3 +184,24 @@ impl World {
         }
     }
 }
+
+#[wasm_bindgen]
+impl World {
+    pub fn set_factory_mode(&mut self, row: usize, col: usize, mode: FactoryMode) {
+        self.log_command(Command::SetFactoryMode { row, col, mode });
+
+        if let Some(entity) = self.core_state.towers_by_pos.get(&(row, col)) {
+            if let Some(factory) = self.core_state.factories.get_mut(entity) {
+                factory.mode = mode;
+            }
+        }
+    }
+
+    pub fn query_factory_mode(&self, tower_entity: u32) -> FactoryMode {
+        self.core_state
+            .factories
+            .get(&tower_entity)
+            .map(|factory| factory.mode)
+            .unwrap_or(FactoryMode::Construct)
+    }
+}
@@ -5,7 +5,7 @@ use serde::{Deserialize, Serialize};
 use crate::{
     build::BuildOrder,
     config::Config,
-    graphics::{SpriteData, SpriteType},
+    graphics::{Layer, SpriteData, SpriteType},
     map::{tile_center, true_row_col, Constants},
     mob::Mob,
     targeting::{find_target, Targeting, Threat},
@@ -262,6 +262,7 @@ impl World {
             let tower = self.core_state.towers.get(entity);
             if let (Some(mob), Some(tower)) = (mob, tower) {
                 let (tower_x, tower_y) = tile_center(tower.row, tower.col);
+                self.render_state.sprite_data.set_layer(Layer::Tower);
                 self.render_state.sprite_data.push(
                     SpriteType::TowerBase as u8,
                     tower_x,
@@ -272,6 +273,12 @@ impl World {
                 );
 
                 let height = falcon.height + frame_fudge * (falcon.height - falcon.old_height);
+                // Perched falcons sit on their towers
+                self.render_state.sprite_data.set_layer(if height > 0.0 {
+                    Layer::Air
+                } else {
+                    Layer::Tower
+                });
                 self.render_state.sprite_data.push(
                     SpriteType::Falcon as u8,
                     mob.x + frame_fudge * (mob.x - mob.old_x),
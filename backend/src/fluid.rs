@@ -1,7 +1,19 @@
-//! Temporarily/permanently abandoned -- fluid simulation might be too
-//! performance intensive.
+//! Hazard field: a density of fire/gas that spreads along the path.
+//!
+//! This started as a full fluid simulation, which was too slow. What's left
+//! is the diffusion step, with only a few relaxation iterations, run only over
+//! the region of the grid that has any density in it.
 
-use crate::map::{Tile, MAP_HEIGHT, MAP_WIDTH, TRUE_MAP_WIDTH};
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+use crate::{
+    config::HazardConfig,
+    health::{Damage, DamageKind},
+    map::{tile_center, Constants, Tile, MAP_HEIGHT, MAP_WIDTH, TRUE_MAP_WIDTH},
+    tower::{TowerStatus, FIRE_INDEX},
+    world::World,
+};
 
 /// Fluid simulation takes place on a coarse grid, but not quite as coarse as
 /// the tile grid for placing towers. Resolution configures how many times finer
@@ -16,166 +28,322 @@ const FLUID_WIDTH: usize = 2 + FLUID_RESOLUTION * MAP_WIDTH;
 const FLUID_HEIGHT: usize = 2 + FLUID_RESOLUTION * MAP_HEIGHT;
 const MAX_VISIBLE_ROW: usize = FLUID_HEIGHT - 2;
 const MAX_VISIBLE_COL: usize = FLUID_WIDTH - 2;
-const FLUID_VISIBLE_AREA: usize = FLUID_RESOLUTION * FLUID_RESOLUTION * MAP_WIDTH * MAP_HEIGHT;
 
-type FluidArr = [f32; FLUID_WIDTH * FLUID_HEIGHT];
+const CELL_SIZE: f32 = f32::TILE_SIZE / FLUID_RESOLUTION as f32;
+
+/// Densities below this are rounded down to 0 so that the field settles and
+/// stops needing updates.
+const EPSILON: f32 = 0.001;
 
+/// Hazard damage is dealt in chunks so that damage numbers stay readable.
+const DAMAGE_PERIOD: u32 = 30;
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct FluidGrid {
-    density: FluidArr,
-    dx: FluidArr,
-    dy: FluidArr,
+    density: Vec<f32>,
+    /// Bounding box of the cells that might have density in them.
+    dirty: Option<Region>,
 }
 
-impl FluidGrid {
-    pub fn new() -> FluidGrid {
-        FluidGrid {
-            density: [0.0; FLUID_WIDTH * FLUID_HEIGHT],
-            dx: [0.0; FLUID_WIDTH * FLUID_HEIGHT],
-            dy: [0.0; FLUID_WIDTH * FLUID_HEIGHT],
+/// Inclusive range of fluid cells.
+#[derive(Serialize, Deserialize, Clone, Copy)]
+struct Region {
+    min_row: usize,
+    max_row: usize,
+    min_col: usize,
+    max_col: usize,
+}
+
+impl Region {
+    fn cell(row: usize, col: usize) -> Region {
+        Region {
+            min_row: row,
+            max_row: row,
+            min_col: col,
+            max_col: col,
+        }
+    }
+
+    fn include(self, row: usize, col: usize) -> Region {
+        Region {
+            min_row: self.min_row.min(row),
+            max_row: self.max_row.max(row),
+            min_col: self.min_col.min(col),
+            max_col: self.max_col.max(col),
+        }
+    }
+
+    /// Grow by a cell in every direction, staying within the visible grid.
+    fn grow(self) -> Region {
+        Region {
+            min_row: (self.min_row - 1).max(1),
+            max_row: (self.max_row + 1).min(MAX_VISIBLE_ROW),
+            min_col: (self.min_col - 1).max(1),
+            max_col: (self.max_col + 1).min(MAX_VISIBLE_COL),
         }
     }
 }
 
-fn add_density(grid: &mut FluidGrid) {
-    grid.density[fluid_pos_to_index(10, 10)] = 20.0;
+impl Default for FluidGrid {
+    fn default() -> Self {
+        FluidGrid {
+            density: vec![0.0; FLUID_WIDTH * FLUID_HEIGHT],
+            dirty: None,
+        }
+    }
 }
 
-const DIFFUSION_FACTOR: f32 = 1.0;
-const TIME_PER_TICK: f32 = 1.0;
-const RELAXATION_ITERATIONS: u32 = 20;
+impl FluidGrid {
+    /// Density at a position in pixels.
+    pub fn density_at(&self, x: f32, y: f32) -> f32 {
+        match pixel_to_fluid_pos(x, y) {
+            Some((row, col)) => self.density[fluid_pos_to_index(row, col)],
+            None => 0.0,
+        }
+    }
 
-fn diffuse(arr: &mut FluidArr, old_arr: &FluidArr, map: &Vec<Tile>) {
-    let a = DIFFUSION_FACTOR * TIME_PER_TICK * FLUID_VISIBLE_AREA as f32;
-    let ix = fluid_pos_to_index;
+    /// Add density at a position in pixels. Density can only exist on path
+    /// tiles.
+    pub fn emit(&mut self, map: &[Tile], x: f32, y: f32, amount: f32) {
+        if let Some((row, col)) = pixel_to_fluid_pos(x, y) {
+            self.emit_cell(map, row, col, amount);
+        }
+    }
 
-    let in_bounds = |row, col| match map[fluid_pos_to_true_tile_index(row, col)] {
-        Tile::Empty | Tile::OutOfBounds => false,
-        _ => true,
-    };
+    /// Add density to every cell of a tile, in visible tile coordinates.
+    fn emit_tile(&mut self, map: &[Tile], row: usize, col: usize, amount: f32) {
+        let top_left = (1 + row * FLUID_RESOLUTION, 1 + col * FLUID_RESOLUTION);
+        for row in top_left.0..top_left.0 + FLUID_RESOLUTION {
+            for col in top_left.1..top_left.1 + FLUID_RESOLUTION {
+                self.emit_cell(map, row, col, amount);
+            }
+        }
+    }
 
-    for _ in 0..RELAXATION_ITERATIONS {
-        for row in 1..=MAX_VISIBLE_ROW {
-            for col in 1..=MAX_VISIBLE_COL {
-                if !in_bounds(row, col) {
+    fn emit_cell(&mut self, map: &[Tile], row: usize, col: usize, amount: f32) {
+        if !is_path(map, row, col) {
+            return;
+        }
+        self.density[fluid_pos_to_index(row, col)] += amount;
+        self.dirty = Some(match self.dirty {
+            Some(region) => region.include(row, col),
+            None => Region::cell(row, col),
+        });
+    }
+
+    /// Spread and decay density for one tick.
+    fn update(&mut self, map: &[Tile], config: &HazardConfig) {
+        let region = match self.dirty {
+            Some(region) => region.grow(),
+            None => return,
+        };
+
+        diffuse(&mut self.density, region, map, config);
+
+        let mut dirty = None;
+        for row in region.min_row..=region.max_row {
+            for col in region.min_col..=region.max_col {
+                let density = &mut self.density[fluid_pos_to_index(row, col)];
+                *density *= 1.0 - config.decay;
+                if *density < EPSILON {
+                    *density = 0.0;
+                } else {
+                    dirty = Some(match dirty {
+                        Some(region) => Region::include(region, row, col),
+                        None => Region::cell(row, col),
+                    });
+                }
+            }
+        }
+        self.dirty = dirty;
+    }
+}
+
+/// Implicit diffusion solved with a few Gauss-Seidel iterations, limited to a
+/// region. Density doesn't flow into or out of cells that aren't on the path.
+fn diffuse(arr: &mut [f32], region: Region, map: &[Tile], config: &HazardConfig) {
+    let a = config.diffusion;
+    let ix = fluid_pos_to_index;
+    let old_arr = arr.to_vec();
+
+    for _ in 0..config.iterations {
+        for row in region.min_row..=region.max_row {
+            for col in region.min_col..=region.max_col {
+                if !is_path(map, row, col) {
                     continue;
                 }
-                // The Jos Stam paper uses arr instead of old_arr for the
-                // terms being multiplied by a because it is iteratively
-                // adjusting arr over and over again. This means iteration
-                // order affects our results (since we are too cheap to
-                // double buffer). To assuage this a bit, we can alternate
-                // iteration order.
-                let (row, col) = if (row + col) % 2 == 0 {
-                    (row, col)
-                } else {
-                    (MAX_VISIBLE_ROW - row + 1, MAX_VISIBLE_COL - col + 1)
-                };
-                arr[ix(row, col)] = old_arr[ix(row, col)]
-                    * a
-                    * (if in_bounds(row + 1, col) {
-                        arr[ix(row + 1, col)]
-                    } else {
-                        arr[ix(row, col)]
-                    } + if in_bounds(row - 1, col) {
-                        arr[ix(row - 1, col)]
-                    } else {
+                let here = arr[ix(row, col)];
+                let neighbor = |row, col| {
+                    if is_path(map, row, col) {
                         arr[ix(row, col)]
-                    } + if in_bounds(row, col + 1) {
-                        arr[ix(row, col + 1)]
                     } else {
-                        arr[ix(row, col)]
-                    } + if in_bounds(row, col - 1) {
-                        arr[ix(row, col - 1)]
-                    } else {
-                        arr[ix(row, col)]
-                    })
-                    / (1.0 + 4.0 * a);
+                        here
+                    }
+                };
+                let neighbors = neighbor(row + 1, col)
+                    + neighbor(row - 1, col)
+                    + neighbor(row, col + 1)
+                    + neighbor(row, col - 1);
+                arr[ix(row, col)] = (old_arr[ix(row, col)] + a * neighbors) / (1.0 + 4.0 * a);
             }
         }
     }
 }
 
-/// Update densities by following velocity backwards in time and averaging the
-/// closest four densities.
-fn advect(
-    density: &mut FluidArr,
-    old_density: &FluidArr,
-    dx: &FluidArr,
-    dy: &FluidArr,
-    map: &Vec<Tile>,
-) {
-    let ix = fluid_pos_to_index;
+impl World {
+    /// Emit density from fire towers and toxic enemies, spread it, then let it
+    /// hurt the walkers standing in it. Damage is credited to the closest fire
+    /// tower that reaches the walker, if any.
+    pub fn update_hazard(&mut self) {
+        let map = &self.level_state.map;
+        let config = &self.config.hazard;
+        let hazard = &mut self.core_state.hazard;
+
+        let mut fires = Vec::new();
+        for (&entity, tower) in &self.core_state.towers {
+            if tower.type_index != FIRE_INDEX || tower.status != TowerStatus::Operational {
+                continue;
+            }
+            let reach = (tower.range / f32::TILE_SIZE).floor() as usize;
+            fires.push(Fire {
+                entity,
+                row: tower.row,
+                col: tower.col,
+                reach,
+            });
+            for row in tower.row.saturating_sub(reach)..=(tower.row + reach).min(MAP_HEIGHT - 1) {
+                for col in tower.col.saturating_sub(reach)..=(tower.col + reach).min(MAP_WIDTH - 1)
+                {
+                    hazard.emit_tile(map, row, col, config.fire_emission * tower.burning);
+                }
+            }
+        }
 
-    for row in 1..=MAX_VISIBLE_ROW {
-        for col in 1..=MAX_VISIBLE_COL {
-            let x = col as f32 - TIME_PER_TICK * MAX_VISIBLE_COL as f32 * dx[ix(row, col)];
-            let y = row as f32 - TIME_PER_TICK * MAX_VISIBLE_ROW as f32 * dy[ix(row, col)];
-            let x = x.max(0.5).min(MAX_VISIBLE_COL as f32 + 0.5);
-            let y = y.max(0.5).min(MAX_VISIBLE_ROW as f32 + 0.5);
-            let col0 = x as usize;
-            let col1 = col0 + 1;
-            let row0 = y as usize;
-            let row1 = row0 + 1;
-            let s1 = x - col0 as f32;
-            let s0 = 1.0 - s1;
-            let t1 = y - row0 as f32;
-            let t0 = 1.0 - t1;
-            density[ix(row, col)] = s0
-                * (t0 * old_density[ix(row0, col0)] + t1 * old_density[ix(row1, col0)])
-                + s1 * (t0 * old_density[ix(row0, col1)] + t1 * old_density[ix(row1, col1)]);
-        }
-    }
-    // Boundary conditions: set out of bounds densities or velocities to 0
-
-    let in_bounds = |row, col| match map[fluid_pos_to_true_tile_index(row, col)] {
-        Tile::Empty | Tile::OutOfBounds => false,
-        _ => true,
-    };
-    for row in 0..FLUID_HEIGHT {
-        for col in 0..FLUID_WIDTH {
-            if !in_bounds(row, col) {
-                density[ix(row, col)] = 0.0;
+        for (entity, walker) in &self.core_state.walkers {
+            let toxic = self.config.get_enemy(walker.enemy_type).toxic;
+            if toxic > 0.0 {
+                if let Some(mob) = self.core_state.mobs.get(entity) {
+                    hazard.emit(map, mob.x, mob.y, toxic);
+                }
+            }
+        }
+
+        hazard.update(map, config);
+
+        if self.core_state.tick % DAMAGE_PERIOD != 0 {
+            return;
+        }
+        for (&entity, walker) in &self.core_state.walkers {
+            if self.config.get_enemy(walker.enemy_type).toxic > 0.0 {
+                // Toxic enemies are immune to their own gas
+                continue;
+            }
+            if let Some(mob) = self.core_state.mobs.get(&entity) {
+                let density = self.core_state.hazard.density_at(mob.x, mob.y);
+                if density > config.threshold {
+                    self.core_state.damage.push(Damage {
+                        target: entity,
+                        amount: config.damage * density.min(1.0) * DAMAGE_PERIOD as f32,
+                        kind: DamageKind::Fire,
+                        source: fire_source(&fires, mob.x, mob.y),
+                    });
+                }
             }
         }
     }
-}
 
-fn update_density(grid: &mut FluidGrid, old_grid: &mut FluidGrid, map: &Vec<Tile>) {
-    add_density(grid);
-    // Diffuse puts updated values into old_grid
-    diffuse(&mut old_grid.density, &grid.density, map);
-    // Advect puts density values back into grid from old_grid
-    advect(
-        &mut grid.density,
-        &old_grid.density,
-        &grid.dx,
-        &grid.dy,
-        map,
-    );
+    /// Fraction of its speed that a walker keeps while standing in the hazard.
+    pub fn hazard_slowdown(&self, enemy_type: usize, x: f32, y: f32) -> f32 {
+        if self.config.get_enemy(enemy_type).toxic > 0.0 {
+            return 1.0;
+        }
+        let density = self.core_state.hazard.density_at(x, y).min(1.0);
+        1.0 - self.config.hazard.slow * density
+    }
 }
 
-#[derive(Clone, Copy, PartialEq, Eq)]
-enum Bounce {
-    NoBounce = 0,
-    BounceX = 1,
-    BounceY = 2,
+#[wasm_bindgen]
+impl World {
+    /// Fill the hazard texture with one byte of intensity per visible cell.
+    pub fn dump_hazard(&mut self) {
+        let texture = &mut self.render_state.hazard_texture;
+        texture.clear();
+        for row in 1..=MAX_VISIBLE_ROW {
+            for col in 1..=MAX_VISIBLE_COL {
+                let density = self.core_state.hazard.density[fluid_pos_to_index(row, col)];
+                texture.push((density.min(1.0) * 255.0) as u8);
+            }
+        }
+    }
+
+    pub fn hazard_texture(&self) -> *const u8 {
+        self.render_state.hazard_texture.as_ptr()
+    }
+
+    pub fn hazard_width(&self) -> usize {
+        MAX_VISIBLE_COL
+    }
+
+    pub fn hazard_height(&self) -> usize {
+        MAX_VISIBLE_ROW
+    }
 }
 
-// Jos Stam calls bounce b. Not sure if the name bounce is accurate.
-fn set_boundary(bounce: Bounce, arr: &mut FluidArr) {
-    let ix = fluid_pos_to_index;
+/// A fire tower and the tiles around it that it sets alight.
+struct Fire {
+    entity: u32,
+    row: usize,
+    col: usize,
+    reach: usize,
+}
 
-    for row in 1..=MAX_VISIBLE_ROW {
-        let sign = if bounce == Bounce::BounceX { -1.0 } else { 1.0 };
-        arr[ix(row, 0)] = sign * arr[ix(row, 1)];
-        arr[ix(row, MAX_VISIBLE_COL + 1)] = sign * arr[ix(row, MAX_VISIBLE_COL)];
+/// The closest fire tower that reaches a position in pixels, which gets the
+/// credit for hazard damage there.
+fn fire_source(fires: &[Fire], x: f32, y: f32) -> Option<u32> {
+    if x < 0.0 || y < 0.0 {
+        return None;
     }
+    let row = (y / f32::TILE_SIZE) as usize;
+    let col = (x / f32::TILE_SIZE) as usize;
+    let mut closest: Option<(f32, u32)> = None;
+    for fire in fires {
+        let reach = fire.reach as isize;
+        if (fire.row as isize - row as isize).abs() > reach
+            || (fire.col as isize - col as isize).abs() > reach
+        {
+            continue;
+        }
+        let (fire_x, fire_y) = tile_center(fire.row, fire.col);
+        let dist_squared = (fire_x - x).powi(2) + (fire_y - y).powi(2);
+        let closer = match closest {
+            Some((closest_dist, _)) => dist_squared < closest_dist,
+            None => true,
+        };
+        if closer {
+            closest = Some((dist_squared, fire.entity));
+        }
+    }
+    closest.map(|(_, entity)| entity)
+}
 
-    for col in 1..=MAX_VISIBLE_COL {
-        let sign = if bounce == Bounce::BounceY { -1.0 } else { 1.0 };
-        arr[ix(0, col)] = sign * arr[ix(1, col)];
-        arr[ix(MAX_VISIBLE_ROW + 1, col)] = sign * arr[ix(MAX_VISIBLE_ROW, col)];
+fn is_path(map: &[Tile], row: usize, col: usize) -> bool {
+    !matches!(
+        map.get(fluid_pos_to_true_tile_index(row, col)),
+        Some(Tile::Empty) | Some(Tile::OutOfBounds) | None
+    )
+}
+
+/// Fluid cell containing a position in pixels, if it's within the visible map.
+fn pixel_to_fluid_pos(x: f32, y: f32) -> Option<(usize, usize)> {
+    if x < 0.0 || y < 0.0 {
+        return None;
+    }
+    let row = 1 + (y / CELL_SIZE) as usize;
+    let col = 1 + (x / CELL_SIZE) as usize;
+    if row > MAX_VISIBLE_ROW || col > MAX_VISIBLE_COL {
+        return None;
     }
+    Some((row, col))
 }
 
 /// Return the tile index (in a TRUE_MAP_WIDTH x TRUE_MAP_HEIGHT grid) that
@@ -183,17 +351,11 @@ fn set_boundary(bounce: Bounce, arr: &mut FluidArr) {
 pub fn fluid_pos_to_true_tile_index(row: usize, col: usize) -> usize {
     let resolution = FLUID_RESOLUTION as isize;
     true_tile_pos_to_index(
-        (2 + (row as isize - 1) / resolution) as usize,
-        (2 + (col as isize - 1) / resolution) as usize,
+        (2 + (row as isize - 1).div_euclid(resolution)) as usize,
+        (2 + (col as isize - 1).div_euclid(resolution)) as usize,
     )
 }
 
-/// Return the top left cell in a given tile. This uses visible tile
-/// coordinates, not true tile coordinates.
-pub fn tile_pos_to_fluid_index(row: usize, col: usize) -> usize {
-    fluid_pos_to_index(1 + row * FLUID_RESOLUTION, 1 + col * FLUID_RESOLUTION)
-}
-
 fn true_tile_pos_to_index(row: usize, col: usize) -> usize {
     row * TRUE_MAP_WIDTH + col
 }
@@ -201,3 +363,39 @@ fn true_tile_pos_to_index(row: usize, col: usize) -> usize {
 fn fluid_pos_to_index(row: usize, col: usize) -> usize {
     row * FLUID_WIDTH + col
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::map::{parse, MAP_0};
+
+    #[test]
+    fn density_spreads_along_path_and_settles() {
+        let map = parse(&MAP_0);
+        let config = HazardConfig::default();
+        let mut grid = FluidGrid::default();
+
+        // Row 1, col 0 of the visible map is on the path
+        grid.emit_tile(&map, 1, 0, 1.0);
+        grid.emit_tile(&map, 0, 0, 1.0);
+        for _ in 0..10 {
+            grid.update(&map, &config);
+        }
+        for row in 1..=MAX_VISIBLE_ROW {
+            for col in 1..=MAX_VISIBLE_COL {
+                let density = grid.density[fluid_pos_to_index(row, col)];
+                assert!(density >= 0.0);
+                if !is_path(&map, row, col) {
+                    assert_eq!(density, 0.0);
+                }
+            }
+        }
+        assert!(grid.density_at(f32::TILE_SIZE * 1.5, f32::TILE_SIZE * 1.5) > 0.0);
+
+        for _ in 0..2000 {
+            grid.update(&map, &config);
+        }
+        assert!(grid.dirty.is_none());
+    }
+}
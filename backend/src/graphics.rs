@@ -1,7 +1,10 @@
 use wasm_bindgen::prelude::*;
 
 use crate::{
-    health::Corpse,
+    blueprint::{BlueprintData, Placement},
+    build_queue::{BuildEtas, BuildQueueData},
+    events::EventData,
+    health::{Corpse, DamageNumber},
     map::tile_center,
     smoke::SmokeTrail,
     tower::Tower,
@@ -10,11 +13,24 @@ use crate::{
 
 #[derive(Default)]
 pub struct RenderState {
+    pub blueprint: Vec<Placement>,
+    pub blueprint_data: BlueprintData,
+    /// Where the path being drawn in the map editor ends, in true tile
+    /// coordinates.
+    pub editor_path_end: Option<(usize, usize)>,
     pub build_progress: BuildProgressData,
+    pub build_etas: Option<BuildEtas>,
+    pub build_queue: BuildQueueData,
     pub corpses: Map<u32, Corpse>,
+    pub damage_number_data: DamageNumberData,
+    pub damage_numbers: Vec<DamageNumber>,
+    pub event_data: EventData,
+    pub hazard_texture: Vec<u8>,
+    pub health_bars: HealthBarData,
     pub preview_tower: Option<Tower>,
     pub smoke_trails: Map<u32, SmokeTrail>,
     pub sprite_data: SpriteData,
+    pub synergy_preview: Vec<u32>,
     pub range_data: RangeData,
 }
 
@@ -35,8 +51,36 @@ pub struct SpriteData {
     pub rotation: Vec<f32>,
     pub alpha: Vec<f32>,
     pub tint: Vec<u32>,
+    pub layer: Vec<u8>,
+    /// Layer for sprites pushed from now on.
+    current_layer: Layer,
 }
 
+/// Sprites are drawn one layer at a time, from back to front. Within a layer,
+/// sprites pushed first get drawn in the back.
+#[wasm_bindgen]
+#[repr(u8)]
+#[derive(Clone, Copy, Default)]
+pub enum Layer {
+    /// The map itself.
+    #[default]
+    Ground,
+    /// Things lying on the path, like corpses.
+    PathDecal,
+    /// Towers and anything attached to them.
+    Tower,
+    /// Enemies and effects that follow them, like shields.
+    Walker,
+    /// Missiles and swallows.
+    Projectile,
+    /// Falcons that are off the ground.
+    Air,
+    /// Target indicators and tower previews, which nothing should cover.
+    Overlay,
+}
+
+const LAYER_COUNT: usize = Layer::Overlay as usize + 1;
+
 impl SpriteData {
     fn clear(&mut self) {
         self.sprite_id.clear();
@@ -45,6 +89,12 @@ impl SpriteData {
         self.rotation.clear();
         self.alpha.clear();
         self.tint.clear();
+        self.layer.clear();
+        self.current_layer = Layer::Ground;
+    }
+
+    pub fn set_layer(&mut self, layer: Layer) {
+        self.current_layer = layer;
     }
 
     pub fn push(&mut self, sprite_id: u8, x: f32, y: f32, rotation: f32, alpha: f32, tint: u32) {
@@ -54,6 +104,35 @@ impl SpriteData {
         self.rotation.push(rotation);
         self.alpha.push(alpha);
         self.tint.push(tint);
+        self.layer.push(self.current_layer as u8);
+    }
+
+    /// Stable sort of the sprites by layer, so that js can draw them in order.
+    fn sort_by_layer(&mut self) {
+        // Counting sort, since there are only a few layers
+        let mut starts = [0; LAYER_COUNT + 1];
+        for &layer in &self.layer {
+            starts[layer as usize + 1] += 1;
+        }
+        for i in 1..starts.len() {
+            starts[i] += starts[i - 1];
+        }
+        let mut order = vec![0; self.layer.len()];
+        for (i, &layer) in self.layer.iter().enumerate() {
+            order[starts[layer as usize]] = i;
+            starts[layer as usize] += 1;
+        }
+
+        fn permute<T: Copy>(values: &mut Vec<T>, order: &[usize]) {
+            *values = order.iter().map(|&i| values[i]).collect();
+        }
+        permute(&mut self.sprite_id, &order);
+        permute(&mut self.x, &order);
+        permute(&mut self.y, &order);
+        permute(&mut self.rotation, &order);
+        permute(&mut self.alpha, &order);
+        permute(&mut self.tint, &order);
+        permute(&mut self.layer, &order);
     }
 }
 
@@ -69,6 +148,7 @@ pub enum SpriteType {
     TowerBase,
     Factory,
     Corpse,
+    Shield,
 }
 
 pub struct RopeData {}
@@ -103,16 +183,22 @@ impl World {
         self.render_state.sprite_data.tint.as_ptr()
     }
 
+    pub fn sprite_layer(&self) -> *const u8 {
+        self.render_state.sprite_data.layer.as_ptr()
+    }
+
     pub fn dump_sprite_data(&mut self, frame_fudge: f32) {
         let frame_fudge = match self.run_state {
             crate::world::RunState::Playing => frame_fudge,
             _ => 0.0,
         };
 
-        // Order matters: sprites pushed first get rendered in the back.
+        // Within a layer, sprites pushed first get rendered in the back.
         self.render_state.sprite_data.clear();
 
+        self.render_state.sprite_data.set_layer(Layer::PathDecal);
         self.dump_corpses();
+        self.render_state.sprite_data.set_layer(Layer::Tower);
         self.dump_factories();
         for (id, targeter) in &self.core_state.swallow_targeters {
             targeter.dump(
@@ -123,6 +209,7 @@ impl World {
             );
         }
         self.dump_missile_towers(frame_fudge);
+        self.render_state.sprite_data.set_layer(Layer::Projectile);
         for swallow in self.core_state.swallow_after_images.values() {
             swallow.dump(&mut self.render_state.sprite_data, frame_fudge);
         }
@@ -135,15 +222,20 @@ impl World {
             );
         }
         self.dump_missiles(frame_fudge);
+        self.render_state.sprite_data.set_layer(Layer::Walker);
         for (id, walker) in &self.core_state.walkers {
             walker.dump(
                 id,
                 &mut self.render_state.sprite_data,
                 &self.core_state.mobs,
+                &self.config,
                 frame_fudge,
             );
         }
+        self.dump_shields(frame_fudge);
+        // Falcons pick their own layers
         self.dump_falcons(frame_fudge);
+        self.render_state.sprite_data.set_layer(Layer::Overlay);
         self.dump_preview_tower();
         for (id, indicator) in &self.core_state.target_indicators {
             indicator.dump(
@@ -154,6 +246,8 @@ impl World {
             );
         }
 
+        self.render_state.sprite_data.sort_by_layer();
+
         // Shift everything half a pixel to account for the 1px borders between
         // tiles.
         for x in &mut self.render_state.sprite_data.x {
@@ -268,10 +362,104 @@ impl World {
     }
 }
 
+#[derive(Default)]
+pub struct HealthBarData {
+    pub x: Vec<f32>,
+    pub y: Vec<f32>,
+    /// Fraction of health remaining.
+    pub health: Vec<f32>,
+}
+
+impl HealthBarData {
+    pub fn clear(&mut self) {
+        self.x.clear();
+        self.y.clear();
+        self.health.clear();
+    }
+
+    pub fn push(&mut self, x: f32, y: f32, health: f32) {
+        self.x.push(x);
+        self.y.push(y);
+        self.health.push(health);
+    }
+}
+
+// Health bars for damaged walkers
+#[wasm_bindgen]
+impl World {
+    pub fn health_bar_count(&self) -> usize {
+        self.render_state.health_bars.health.len()
+    }
+
+    pub fn health_bar(&self) -> *const f32 {
+        self.render_state.health_bars.health.as_ptr()
+    }
+
+    pub fn health_bar_x(&self) -> *const f32 {
+        self.render_state.health_bars.x.as_ptr()
+    }
+
+    pub fn health_bar_y(&self) -> *const f32 {
+        self.render_state.health_bars.y.as_ptr()
+    }
+}
+
+#[derive(Default)]
+pub struct DamageNumberData {
+    pub x: Vec<f32>,
+    pub y: Vec<f32>,
+    pub amount: Vec<f32>,
+    pub alpha: Vec<f32>,
+}
+
+impl DamageNumberData {
+    pub fn clear(&mut self) {
+        self.x.clear();
+        self.y.clear();
+        self.amount.clear();
+        self.alpha.clear();
+    }
+
+    pub fn push(&mut self, x: f32, y: f32, amount: f32, alpha: f32) {
+        self.x.push(x);
+        self.y.push(y);
+        self.amount.push(amount);
+        self.alpha.push(alpha);
+    }
+}
+
+// Floating numbers for recently dealt damage
+#[wasm_bindgen]
+impl World {
+    pub fn damage_number_count(&self) -> usize {
+        self.render_state.damage_number_data.amount.len()
+    }
+
+    pub fn damage_number(&self) -> *const f32 {
+        self.render_state.damage_number_data.amount.as_ptr()
+    }
+
+    pub fn damage_number_x(&self) -> *const f32 {
+        self.render_state.damage_number_data.x.as_ptr()
+    }
+
+    pub fn damage_number_y(&self) -> *const f32 {
+        self.render_state.damage_number_data.y.as_ptr()
+    }
+
+    pub fn damage_number_alpha(&self) -> *const f32 {
+        self.render_state.damage_number_data.alpha.as_ptr()
+    }
+}
+
 #[wasm_bindgen]
 extern "C" {
     pub fn render_path_tile(row: usize, col: usize);
     pub fn render_path_border(row: usize, col: usize, horizontal: bool);
+    /// Terrain is given as the discriminant of map::Terrain.
+    pub fn render_terrain(row: usize, col: usize, terrain: u8);
+    /// Remove everything drawn by the other map render functions.
+    pub fn clear_map();
 
     pub fn create_smoke_trail(id: u32, max_length: usize);
     pub fn render_smoke_trail(id: u32, x_ptr: *const f32, y_ptr: *const f32);
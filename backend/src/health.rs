@@ -1,8 +1,13 @@
 use std::f32::consts::PI;
 
 use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
 
-use crate::{graphics::SpriteType, world::World};
+use crate::{
+    events::{Event, EventType},
+    graphics::SpriteType,
+    world::World,
+};
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Health {
@@ -10,6 +15,27 @@ pub struct Health {
     pub max_health: f32,
 }
 
+/// Damage waiting to be dealt to an entity.
+///
+/// Anything that hurts enemies queues damage instead of subtracting health
+/// directly. That way shields and other damage modifiers only need to be
+/// handled in one place.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Damage {
+    pub target: u32,
+    pub amount: f32,
+    pub kind: DamageKind,
+    /// Tower entity that dealt the damage, if any.
+    pub source: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum DamageKind {
+    Explosive,
+    Physical,
+    Fire,
+}
+
 #[derive(Default)]
 pub struct Corpse {
     age: u32,
@@ -19,6 +45,18 @@ pub struct Corpse {
 
 const MAX_CORPSE_AGE: u32 = 60;
 
+/// Damage dealt to an entity, shown as a number floating above it.
+pub struct DamageNumber {
+    age: u32,
+    x: f32,
+    y: f32,
+    amount: f32,
+}
+
+const MAX_DAMAGE_NUMBER_AGE: u32 = 45;
+/// Pixels per tick that damage numbers float upwards.
+const DAMAGE_NUMBER_RISE: f32 = 0.5;
+
 impl Health {
     pub fn new(max_health: f32) -> Health {
         Health {
@@ -29,6 +67,61 @@ impl Health {
 }
 
 impl World {
+    /// Deal all queued damage. Immunities cancel damage entirely, and shields
+    /// absorb damage before health does.
+    pub fn apply_damage(&mut self) {
+        for damage_number in &mut self.render_state.damage_numbers {
+            damage_number.age += 1;
+        }
+        self.render_state
+            .damage_numbers
+            .retain(|damage_number| damage_number.age < MAX_DAMAGE_NUMBER_AGE);
+
+        let wave = self.core_state.wave_spawner.current_wave();
+        for Damage {
+            target,
+            mut amount,
+            kind,
+            source,
+        } in self.core_state.damage.drain(..)
+        {
+            if let Some(boss) = self.core_state.bosses.get(&target) {
+                if boss.immune_to.contains(&kind) {
+                    continue;
+                }
+            }
+            let total = amount;
+            // Damage that actually went into shields or health
+            let mut dealt = 0.0;
+            let mut kill = false;
+            if let Some(shield) = self.core_state.shields.get_mut(&target) {
+                let absorbed = amount.min(shield.amount);
+                shield.amount -= absorbed;
+                amount -= absorbed;
+                dealt += absorbed;
+            }
+            if let Some(health) = self.core_state.health.get_mut(&target) {
+                let remaining = health.curr_health.max(0.0);
+                health.curr_health -= amount;
+                dealt += amount.min(remaining);
+                kill = remaining > 0.0 && health.curr_health <= 0.0;
+            }
+            if let Some(source) = source {
+                self.core_state
+                    .stats
+                    .record_hit(source, wave, dealt, total - dealt, kill);
+            }
+            if let Some(mob) = self.core_state.mobs.get(&target) {
+                self.render_state.damage_numbers.push(DamageNumber {
+                    age: 0,
+                    x: mob.x,
+                    y: mob.y,
+                    amount: total,
+                });
+            }
+        }
+    }
+
     pub fn handle_dead(&mut self) {
         // Age corpses
         let mut trash = Vec::new();
@@ -60,16 +153,39 @@ impl World {
             }
         }
         for entity in graveyard {
-            self.core_state.health.remove(&entity);
-            self.core_state.impulses.remove(&entity);
-            self.core_state.mobs.remove(&entity);
-            self.core_state.health.remove(&entity);
-            self.core_state.target_indicators.remove(&entity);
-            self.core_state.threats.remove(&entity);
-            self.core_state.walkers.remove(&entity);
+            let walker = self.core_state.walkers.get(&entity);
+            let mob = self.core_state.mobs.get(&entity);
+            if let (Some(walker), Some(mob)) = (walker, mob) {
+                let bounty = self.config.get_enemy(walker.enemy_type).bounty
+                    * self.core_state.difficulty.bounty;
+                let (x, y) = (mob.x, mob.y);
+                self.emit(Event {
+                    event_type: EventType::EnemyKilled,
+                    entity,
+                    x,
+                    y,
+                    value: bounty,
+                });
+            }
+            self.split_walker(entity);
+            self.remove_enemy(entity);
         }
     }
 
+    /// Remove an enemy and all of its components.
+    pub fn remove_enemy(&mut self, entity: u32) {
+        self.core_state.bosses.remove(&entity);
+        self.core_state.health.remove(&entity);
+        self.core_state.healers.remove(&entity);
+        self.core_state.impulses.remove(&entity);
+        self.core_state.mobs.remove(&entity);
+        self.core_state.shield_bearers.remove(&entity);
+        self.core_state.shields.remove(&entity);
+        self.core_state.target_indicators.remove(&entity);
+        self.core_state.threats.remove(&entity);
+        self.core_state.walkers.remove(&entity);
+    }
+
     pub fn dump_corpses(&mut self) {
         for (_, corpse) in &self.render_state.corpses {
             let progress = corpse.age as f32 / MAX_CORPSE_AGE as f32;
@@ -85,3 +201,48 @@ impl World {
         }
     }
 }
+
+#[wasm_bindgen]
+impl World {
+    pub fn dump_health_bars(&mut self, frame_fudge: f32) {
+        let frame_fudge = match self.run_state {
+            crate::world::RunState::Playing => frame_fudge,
+            _ => 0.0,
+        };
+        self.render_state.health_bars.clear();
+        for entity in self.core_state.walkers.keys() {
+            if let (Some(health), Some(mob)) = (
+                self.core_state.health.get(entity),
+                self.core_state.mobs.get(entity),
+            ) {
+                if health.curr_health >= health.max_health || health.curr_health <= 0.0 {
+                    continue;
+                }
+                self.render_state.health_bars.push(
+                    mob.x + frame_fudge * (mob.x - mob.old_x),
+                    mob.y + frame_fudge * (mob.y - mob.old_y),
+                    health.curr_health / health.max_health,
+                );
+            }
+        }
+    }
+
+    pub fn dump_damage_numbers(&mut self, frame_fudge: f32) {
+        let frame_fudge = match self.run_state {
+            crate::world::RunState::Playing => frame_fudge,
+            _ => 0.0,
+        };
+        let data = &mut self.render_state.damage_number_data;
+        data.clear();
+        for damage_number in &self.render_state.damage_numbers {
+            let age = damage_number.age as f32 + frame_fudge;
+            let progress = age / MAX_DAMAGE_NUMBER_AGE as f32;
+            data.push(
+                damage_number.x,
+                damage_number.y - age * DAMAGE_NUMBER_RISE,
+                damage_number.amount,
+                1.0 - progress * progress,
+            );
+        }
+    }
+}
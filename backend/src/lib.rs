@@ -1,21 +1,34 @@
+mod aura;
+mod blueprint;
+mod boss;
 mod build;
+mod build_queue;
 mod collision;
 mod config;
 mod distance;
 mod ease;
+mod editor;
+mod endless;
+mod events;
 mod explosion;
 mod factory;
 mod falcon;
+mod fluid;
 mod graphics;
 mod health;
 mod map;
 mod missile;
 mod mob;
 mod pusillanimous;
+mod rng;
 mod smoke;
+mod snapshot;
 mod spatial_index;
+mod stats;
 mod swallow;
+mod synergy;
 mod targeting;
+mod timeline;
 mod tower;
 mod walker;
 mod waves;
@@ -1,6 +1,7 @@
 pub mod distances;
+pub mod generate;
 
-use crate::graphics::{render_path_border, render_path_tile};
+use crate::graphics::{render_path_border, render_path_tile, render_terrain};
 
 pub const MAP_WIDTH: usize = 22;
 pub const MAP_HEIGHT: usize = 18;
@@ -34,6 +35,11 @@ impl Constants for f32 {
 // n represents a path heading north
 // v represents a path heading south
 // x represents a turn in the path
+// N, S, E and W represent muddy paths heading north, south, east and west
+// * represents rocks, where nothing can be built
+// ^ represents high ground, which gives towers more range
+// % represents rich soil, where trees grow faster
+// ~ represents water, where only some towers can be built
 
 pub const MAP_0: [&str; TRUE_MAP_HEIGHT] = [
     "##########################",
@@ -156,6 +162,30 @@ pub enum Tile {
     WestToSouth,
 }
 
+/// What a tile is like apart from whether it's on the path. Path tiles can
+/// only be plain or mud.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Terrain {
+    Plain,
+    Rock,
+    HighGround,
+    RichSoil,
+    Water,
+    Mud,
+}
+
+/// Muddy paths work like regular paths apart from slowing enemies down, so
+/// this maps their glyphs to the regular ones.
+pub fn path_glyph(char: char) -> char {
+    match char {
+        'N' => 'n',
+        'S' => 'v',
+        'E' => '>',
+        'W' => '<',
+        _ => char,
+    }
+}
+
 /// Turn the textual representation of the map into a more convenient
 /// representation for the computer. Specifically, this function figures out
 /// which way corners turn.
@@ -164,14 +194,15 @@ pub fn parse(map_str: &[&str]) -> Vec<Tile> {
 
     for (row, row_str) in map_str.iter().enumerate() {
         for (col, char) in row_str.chars().enumerate() {
-            parsed_map.push(match char {
-                ' ' => Tile::Empty,
+            parsed_map.push(match path_glyph(char) {
+                ' ' | '*' | '^' | '%' | '~' => Tile::Empty,
                 'n' => Tile::North,
                 'v' => Tile::South,
                 '>' => Tile::East,
                 '<' => Tile::West,
                 'x' => {
-                    let get_char = |row: usize, col: usize| map_str[row].as_bytes()[col] as char;
+                    let get_char =
+                        |row: usize, col: usize| path_glyph(map_str[row].as_bytes()[col] as char);
                     if get_char(row - 1, col) == 'v' {
                         if get_char(row, col - 1) == '<' {
                             Tile::SouthToWest
@@ -206,6 +237,26 @@ pub fn parse(map_str: &[&str]) -> Vec<Tile> {
     parsed_map
 }
 
+/// Parse the terrain layer of the textual representation of a map.
+pub fn parse_terrain(map_str: &[&str]) -> Vec<Terrain> {
+    map_str
+        .iter()
+        .flat_map(|row_str| row_str.chars())
+        .map(glyph_terrain)
+        .collect()
+}
+
+pub fn glyph_terrain(char: char) -> Terrain {
+    match char {
+        '*' => Terrain::Rock,
+        '^' => Terrain::HighGround,
+        '%' => Terrain::RichSoil,
+        '~' => Terrain::Water,
+        'N' | 'S' | 'E' | 'W' => Terrain::Mud,
+        _ => Terrain::Plain,
+    }
+}
+
 /// A tile counts as an entrance tile if it is just out of the visible area and
 /// points inward.
 pub fn entrances(map: &[Tile]) -> Vec<(usize, usize)> {
@@ -246,6 +297,8 @@ pub fn entrances(map: &[Tile]) -> Vec<(usize, usize)> {
 
 /// Whether two adjacent tiles are separated by a border, like between the path
 /// and an empty tile or between an empty tile and the edge of the visible map.
+/// Rocks and water are bordered like the path, since they're also where the
+/// player can't freely build.
 /// In the future, we may want to allow for borders between path tiles, for
 /// example at tight u-turns. But for now we can ignore that complexity.
 ///
@@ -253,22 +306,33 @@ pub fn entrances(map: &[Tile]) -> Vec<(usize, usize)> {
 /// future implementations might need to know the positions of tile a and b.
 pub fn has_border(
     map: &[Tile],
+    terrain: &[Terrain],
     true_row_a: usize,
     true_col_a: usize,
     true_row_b: usize,
     true_col_b: usize,
 ) -> bool {
-    let tile_a = map.get(true_row_a * TRUE_MAP_WIDTH + true_col_a);
-    let tile_b = map.get(true_row_b * TRUE_MAP_WIDTH + true_col_b);
+    let index_a = true_row_a * TRUE_MAP_WIDTH + true_col_a;
+    let index_b = true_row_b * TRUE_MAP_WIDTH + true_col_b;
+    let tile_a = map.get(index_a);
+    let tile_b = map.get(index_b);
+    let is_obstacle = |index| {
+        matches!(
+            terrain.get(index),
+            Some(Terrain::Rock) | Some(Terrain::Water)
+        )
+    };
 
     (tile_a == Some(&Tile::OutOfBounds))
         || (tile_b == Some(&Tile::OutOfBounds))
         || ((tile_a == Some(&Tile::Empty)) ^ (tile_b == Some(&Tile::Empty)))
+        || terrain.get(index_a) != terrain.get(index_b)
+            && (is_obstacle(index_a) || is_obstacle(index_b))
 }
 
 /// Call the external render functions. Only do this once per level, not once
 /// per frame.
-pub fn render_map(map: &[Tile]) {
+pub fn render_map(map: &[Tile], terrain: &[Terrain]) {
     // Render the map
     for row in 0..MAP_HEIGHT {
         for col in 0..MAP_WIDTH {
@@ -278,6 +342,10 @@ pub fn render_map(map: &[Tile]) {
                 Tile::Empty => {}
                 _ => render_path_tile(row, col),
             }
+            match terrain[true_row * TRUE_MAP_WIDTH + true_col] {
+                Terrain::Plain => {}
+                kind => render_terrain(row, col, kind as u8),
+            }
         }
     }
     // And the borders of the path
@@ -285,7 +353,7 @@ pub fn render_map(map: &[Tile]) {
         for col in 0..MAP_WIDTH {
             let true_row = row + 2;
             let true_col = col + 2;
-            if has_border(map, true_row - 1, true_col, true_row, true_col) {
+            if has_border(map, terrain, true_row - 1, true_col, true_row, true_col) {
                 render_path_border(row, col, true);
             }
         }
@@ -294,7 +362,7 @@ pub fn render_map(map: &[Tile]) {
         for col in 1..MAP_WIDTH {
             let true_row = row + 2;
             let true_col = col + 2;
-            if has_border(map, true_row, true_col - 1, true_row, true_col) {
+            if has_border(map, terrain, true_row, true_col - 1, true_row, true_col) {
                 render_path_border(row, col, false);
             }
         }
@@ -327,3 +395,50 @@ pub fn in_bounds(x: f32, y: f32) -> bool {
     let height = MAP_HEIGHT as f32 * f32::TILE_SIZE;
     x >= 0.0 && y >= 0.0 && x < width && y < height
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// MAP_0 with some of every kind of terrain.
+    const TERRAIN_MAP: [&str; TRUE_MAP_HEIGHT] = [
+        "##########################",
+        "##########################",
+        "##                      ##",
+        "#>>>x x>>>x    x>>>x x>>>#",
+        "#>>xv nx>xv    nx>xv nx>>#",
+        "## vx>xn vv    nn vx>xn ##",
+        "## x>>>x vv    nn x>>>x ##",
+        "##       vv    nn       ##",
+        "## x<<<x vv    nn x<<<x ##",
+        "## vx<xn vv    nn vx<xn ##",
+        "## vv nx<xv    nx<xv nn ##",
+        "## vv x<<<x    x<<<x nn ##",
+        "## vv  ^^  **  ~~ %% nn ##",
+        "## vv  ^^      ~~    nn ##",
+        "## vv  x>>>x  x>>>x  nn ##",
+        "## vv  nx>xv  nx>xv  nn ##",
+        "## vv  nn vx>>xn vv  nn ##",
+        "## vx>>xn x>EE>x vx>>xn ##",
+        "## x>>>>x        x>>>>x ##",
+        "##                      ##",
+        "##########################",
+        "##########################",
+    ];
+
+    #[test]
+    fn mud_is_a_path_with_terrain() {
+        let map = parse(&TERRAIN_MAP);
+        let terrain = parse_terrain(&TERRAIN_MAP);
+        let index = |row: usize, col: usize| row * TRUE_MAP_WIDTH + col;
+
+        // The muddy stretch in row 17 still leads into its corners
+        assert!(map[index(17, 12)] == Tile::East);
+        assert!(terrain[index(17, 12)] == Terrain::Mud);
+        assert!(map[index(17, 15)] == Tile::EastToNorth);
+        assert!(terrain[index(17, 15)] == Terrain::Plain);
+
+        assert!(map[index(12, 11)] == Tile::Empty);
+        assert!(terrain[index(12, 11)] == Terrain::Rock);
+    }
+}
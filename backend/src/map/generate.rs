@@ -0,0 +1,373 @@
+//! Generate maps from a seed, for endless mode and daily challenges.
+//!
+//! Paths are two tiles wide, like in the hand made maps, so we lay them out on
+//! a coarse grid of 2x2 cells. Paths only turn on every other cell, the nodes,
+//! and the cells between nodes are straight. That way, parts of the path that
+//! don't follow each other directly always have a gap between them.
+//!
+//! Walkers' distances to the entrance and exit only add up to the same value
+//! everywhere on the path if the path turns left as often as it turns right,
+//! so every path leaves the map heading the same way it came in.
+
+use crate::rng::Rng;
+
+use super::{MAP_HEIGHT, MAP_WIDTH, TRUE_MAP_HEIGHT, TRUE_MAP_WIDTH};
+
+const NODE_ROWS: isize = (MAP_HEIGHT as isize / 2 + 1) / 2;
+const NODE_COLS: isize = (MAP_WIDTH as isize / 2 + 1) / 2;
+
+/// Give up on finding a path from a start after this many steps of search.
+const SEARCH_BUDGET: u32 = 20_000;
+
+/// How many times to pick new starting points before giving up on a map.
+const MAX_ATTEMPTS: u32 = 32;
+
+/// A direction as (d_row, d_col).
+type Heading = (isize, isize);
+
+const HEADINGS: [Heading; 4] = [(-1, 0), (1, 0), (0, 1), (0, -1)];
+
+pub struct MapSettings {
+    /// How many separate paths there are, each with its own entrance and exit.
+    pub paths: usize,
+    /// Nodes on each path. Each node adds four tiles to the path's length,
+    /// apart from the last one, which adds two.
+    pub length: usize,
+    /// Turns on each path. Has to be even, since paths leave the map heading
+    /// the way they came in.
+    pub turns: usize,
+}
+
+impl Default for MapSettings {
+    fn default() -> Self {
+        MapSettings {
+            paths: 1,
+            length: 14,
+            turns: 6,
+        }
+    }
+}
+
+/// A path through the nodes, in order.
+type Path = Vec<(isize, isize)>;
+
+fn in_bounds((row, col): (isize, isize)) -> bool {
+    row >= 0 && col >= 0 && row < NODE_ROWS && col < NODE_COLS
+}
+
+fn step((row, col): (isize, isize), heading: Heading) -> (isize, isize) {
+    (row + heading.0, col + heading.1)
+}
+
+/// Nodes to go through before a node would leave the map going this way.
+fn nodes_to_edge((row, col): (isize, isize), heading: Heading) -> isize {
+    match heading {
+        (-1, 0) => row,
+        (1, 0) => NODE_ROWS - 1 - row,
+        (0, 1) => NODE_COLS - 1 - col,
+        _ => col,
+    }
+}
+
+/// +1 for turning one way, -1 for the other, 0 for going straight.
+fn rotation(from: Heading, to: Heading) -> isize {
+    from.0 * to.1 - from.1 * to.0
+}
+
+struct Search<'a> {
+    rng: &'a mut Rng,
+    settings: &'a MapSettings,
+    /// The way paths enter and leave the map.
+    heading: Heading,
+    occupied: Vec<bool>,
+    budget: u32,
+}
+
+impl Search<'_> {
+    fn occupied(&self, (row, col): (isize, isize)) -> bool {
+        self.occupied[(row * NODE_COLS + col) as usize]
+    }
+
+    fn set_occupied(&mut self, (row, col): (isize, isize), occupied: bool) {
+        self.occupied[(row * NODE_COLS + col) as usize] = occupied;
+    }
+
+    /// Depth first search for the rest of a path, trying directions in random
+    /// order. The path so far is heading `facing` and has turned `turns`
+    /// times, `net_rotation` of which is net rotation.
+    fn extend(
+        &mut self,
+        path: &mut Path,
+        facing: Heading,
+        turns: usize,
+        net_rotation: isize,
+    ) -> bool {
+        if self.budget == 0 {
+            return false;
+        }
+        self.budget -= 1;
+
+        let node = path[path.len() - 1];
+        if path.len() == self.settings.length {
+            // Leave the map the way we came in
+            let last_turn = rotation(facing, self.heading);
+            return nodes_to_edge(node, self.heading) == 0
+                && facing != (-self.heading.0, -self.heading.1)
+                && turns + (last_turn != 0) as usize == self.settings.turns
+                && net_rotation + last_turn == 0;
+        }
+
+        let mut headings = HEADINGS;
+        for i in (1..headings.len()).rev() {
+            headings.swap(i, self.rng.below(i + 1));
+        }
+        for &heading in &headings {
+            let next = step(node, heading);
+            let turn = rotation(facing, heading);
+            let turns = turns + (heading != facing) as usize;
+            let remaining = (self.settings.length - path.len() - 1) as isize;
+            if !in_bounds(next)
+                || self.occupied(next)
+                || heading == (-facing.0, -facing.1)
+                || turns > self.settings.turns
+                || nodes_to_edge(next, self.heading) > remaining
+            {
+                continue;
+            }
+            path.push(next);
+            self.set_occupied(next, true);
+            if self.extend(path, heading, turns, net_rotation + turn) {
+                return true;
+            }
+            self.set_occupied(next, false);
+            path.pop();
+        }
+        false
+    }
+
+    /// Find a path from a random free node on the edge that paths come in
+    /// from.
+    fn find_path(&mut self) -> Option<Path> {
+        let back = (-self.heading.0, -self.heading.1);
+        let starts: Vec<_> = (0..NODE_ROWS)
+            .flat_map(|row| (0..NODE_COLS).map(move |col| (row, col)))
+            .filter(|&node| nodes_to_edge(node, back) == 0 && !self.occupied(node))
+            .collect();
+        if starts.is_empty() {
+            return None;
+        }
+        let start = starts[self.rng.below(starts.len())];
+
+        self.budget = SEARCH_BUDGET;
+        let mut path = vec![start];
+        self.set_occupied(start, true);
+        if self.extend(&mut path, self.heading, 0, 0) {
+            Some(path)
+        } else {
+            self.set_occupied(start, false);
+            None
+        }
+    }
+}
+
+/// Heading glyph, as in the textual representation of maps.
+fn glyph(heading: Heading) -> char {
+    match heading {
+        (-1, 0) => 'n',
+        (1, 0) => 'v',
+        (0, 1) => '>',
+        _ => '<',
+    }
+}
+
+fn reverse(heading: Heading) -> Heading {
+    (-heading.0, -heading.1)
+}
+
+/// Whether an offset within a 2x2 cell is on the cell's edge facing away from
+/// a heading.
+fn on_back_edge((d_row, d_col): (isize, isize), heading: Heading) -> bool {
+    let back = |d: isize| if d > 0 { 0 } else { 1 };
+    if heading.0 != 0 {
+        d_row == back(heading.0)
+    } else {
+        d_col == back(heading.1)
+    }
+}
+
+/// Draw a path onto glyphs. Every tile points the way its cell leads, except
+/// at corners, where the tile on the outside of the turn carries on one more
+/// tile before turning.
+fn draw_path(glyphs: &mut [char], path: &[(isize, isize)], heading: Heading) {
+    // Include the cells between nodes
+    let mut cells = Vec::with_capacity(2 * path.len());
+    for (i, &(row, col)) in path.iter().enumerate() {
+        if i > 0 {
+            let (prev_row, prev_col) = path[i - 1];
+            cells.push((row + prev_row, col + prev_col));
+        }
+        cells.push((2 * row, 2 * col));
+    }
+
+    let mut set = |true_row: isize, true_col: isize, heading: Heading| {
+        glyphs[(true_row * TRUE_MAP_WIDTH as isize + true_col) as usize] = glyph(heading);
+    };
+    for (i, &(row, col)) in cells.iter().enumerate() {
+        let towards = |(other_row, other_col): (isize, isize)| (other_row - row, other_col - col);
+        let entering = match i {
+            0 => heading,
+            _ => reverse(towards(cells[i - 1])),
+        };
+        let leaving = match cells.get(i + 1) {
+            Some(&next) => towards(next),
+            None => heading,
+        };
+
+        for &offset in &[(0, 0), (0, 1), (1, 0), (1, 1)] {
+            let true_row = 2 + 2 * row + offset.0;
+            let true_col = 2 + 2 * col + offset.1;
+            let on_entry_edge = on_back_edge(offset, entering);
+            let outside_of_turn = on_entry_edge && on_back_edge(offset, leaving);
+            if entering != leaving && outside_of_turn {
+                set(true_row, true_col, entering);
+            } else {
+                set(true_row, true_col, leaving);
+            }
+
+            // Entrance and exit tiles just outside the visible map
+            if i == 0 && on_entry_edge {
+                set(true_row - entering.0, true_col - entering.1, entering);
+            }
+            if i == cells.len() - 1 && on_back_edge(offset, reverse(leaving)) {
+                set(true_row + leaving.0, true_col + leaving.1, leaving);
+            }
+        }
+    }
+}
+
+/// Generate a map's glyphs from a seed, or None if no map with these settings
+/// turned up. Corners are stored as the way they turn, like in the map
+/// editor, and editor::tiles turns them into tiles.
+pub fn generate(seed: u64, settings: &MapSettings) -> Option<Vec<char>> {
+    if settings.paths == 0 || settings.length == 0 || settings.turns % 2 == 1 {
+        return None;
+    }
+    let mut rng = Rng::new(seed);
+    for _ in 0..MAX_ATTEMPTS {
+        let heading = HEADINGS[rng.below(HEADINGS.len())];
+        let mut search = Search {
+            rng: &mut rng,
+            settings,
+            heading,
+            occupied: vec![false; (NODE_ROWS * NODE_COLS) as usize],
+            budget: 0,
+        };
+        let paths: Option<Vec<Path>> = (0..settings.paths).map(|_| search.find_path()).collect();
+        let paths = match paths {
+            Some(paths) => paths,
+            None => continue,
+        };
+
+        let mut glyphs = Vec::with_capacity(TRUE_MAP_WIDTH * TRUE_MAP_HEIGHT);
+        for true_row in 0..TRUE_MAP_HEIGHT {
+            for true_col in 0..TRUE_MAP_WIDTH {
+                let visible = (2..MAP_HEIGHT + 2).contains(&true_row)
+                    && (2..MAP_WIDTH + 2).contains(&true_col);
+                glyphs.push(if visible { ' ' } else { '#' });
+            }
+        }
+        for path in &paths {
+            draw_path(&mut glyphs, path, heading);
+        }
+        return Some(glyphs);
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::distances::{
+        calc_dist_from_entrance, calc_dist_from_exit, generate_dist_from_entrance,
+        generate_dist_from_exit,
+    };
+    use super::super::Constants;
+    use super::*;
+
+    use crate::editor::{export, tiles, validate};
+
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn generated_maps_are_valid(
+            seed in any::<u64>(),
+            paths in 1_usize..3,
+            half_turns in 1_usize..4,
+            tile in any::<prop::sample::Index>(),
+            x in 0_f32..f32::TILE_SIZE,
+            y in 0_f32..f32::TILE_SIZE,
+        ) {
+            let settings = MapSettings {
+                paths,
+                length: 10,
+                turns: 2 * half_turns,
+            };
+            let glyphs = generate(seed, &settings);
+            prop_assume!(glyphs.is_some());
+            let glyphs = glyphs.unwrap();
+            prop_assert_eq!(validate(&glyphs), Ok(()));
+
+            // Every path has the same length, so distances add up to the same
+            // value anywhere on the path
+            let map = &tiles(&glyphs);
+            let path_tiles: Vec<_> = (0..MAP_HEIGHT)
+                .flat_map(|row| (0..MAP_WIDTH).map(move |col| (row, col)))
+                .filter(|&(row, col)| glyphs[(row + 2) * TRUE_MAP_WIDTH + col + 2] != ' ')
+                .collect();
+            let (row, col) = path_tiles[tile.index(path_tiles.len())];
+            let x = col as f32 * f32::TILE_SIZE + x;
+            let y = row as f32 * f32::TILE_SIZE + y;
+
+            let entrance_dist =
+                calc_dist_from_entrance(map, &generate_dist_from_entrance(map), x, y);
+            let exit_dist = calc_dist_from_exit(map, &generate_dist_from_exit(map), x, y);
+            let correct_distance = (4 * settings.length - 1) as f32 * f32::TILE_SIZE;
+            let net_distance = entrance_dist + exit_dist;
+            prop_assert!((net_distance - correct_distance).abs() < 1e-3,
+                "Net distance was {}, should be {}", net_distance, correct_distance);
+        }
+    }
+
+    /// What seed 7 generates with the default settings. Daily challenges rely
+    /// on every player getting the same map from a seed.
+    const SEED_7: [&str; 22] = [
+        "##########################",
+        "##########################",
+        "##        x<<<<<<<<<<<<x##",
+        "##        vx<<<<<<<<<<xn##",
+        "##        vv          nn##",
+        "##        vv          nn##",
+        "##        vv          nn##",
+        "##        vv          nn##",
+        "##        vv          nn##",
+        "##        vv          nn##",
+        "#<<<<<<<<<xv      x>>>xn##",
+        "#<<<<<<<<<<x      nx>>>x##",
+        "##                nn    ##",
+        "##                nn    ##",
+        "##                nn    ##",
+        "##                nn    ##",
+        "##                nn    ##",
+        "##                nn    ##",
+        "##                nx<<<<<#",
+        "##                x<<<<<<#",
+        "##########################",
+        "##########################",
+    ];
+
+    #[test]
+    fn same_seed_same_map() {
+        let glyphs = generate(7, &MapSettings::default()).unwrap();
+        assert_eq!(export(&glyphs), SEED_7.join("\n"));
+    }
+}
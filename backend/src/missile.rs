@@ -9,27 +9,19 @@ use serde::{Deserialize, Serialize};
 
 use crate::{
     build::BuildOrder,
-    config::Config,
+    config::{Config, MissileConfig, UpgradeEffect},
     ease::ease_to_x_geometric,
-    explosion::spawn_explosion,
+    explosion::{spawn_explosion, Explosion},
     graphics::SpriteType,
     map::{tile_center, Constants},
     mob::Mob,
     smoke::spawn_smoke_trail,
-    targeting::{find_target, Targeting, Threat, THREAT_DISTANCE},
+    targeting::{find_spread_target, find_target, Targeting, Threat, THREAT_DISTANCE},
     tower::{create_tower, Tower, TowerStatus, MISSILE_INDEX},
     walker::STANDARD_ENEMY_RADIUS,
-    world::{Map, World},
+    world::{EntityIds, Map, World},
 };
 
-const MAX_SPEED: f32 = 5.0;
-const MAX_TURN_SPEED: f32 = 0.13;
-const ACCELERATION: f32 = 0.31;
-const ROTATION_ACCEL: f32 = 0.05;
-
-const TOWER_MAX_TURN_SPEED: f32 = 0.08;
-const TOWER_ROTATION_ACCEL: f32 = 0.002;
-
 pub const MISSILE_WIDTH: f32 = 5.0;
 pub const MISSILE_LENGTH: f32 = 10.0;
 
@@ -38,8 +30,30 @@ pub struct MissileSpawner {
     pub reload_cost: u32,
     pub rotation: f32,
     pub rotation_speed: f32,
-    left_reload_countdown: u32,
-    right_reload_countdown: u32,
+    /// Ticks until the next salvo.
+    reload_countdown: u32,
+    /// Ticks since each launcher fired, counting down from the reload cost,
+    /// from left to right. Only used for animation.
+    launchers: Vec<u32>,
+    next_launcher: usize,
+    /// Missiles fired at once, each from its own launcher.
+    salvo: u32,
+    cluster: bool,
+    homing: bool,
+}
+
+impl MissileSpawner {
+    pub fn upgrade(&mut self, effect: &UpgradeEffect) {
+        match *effect {
+            UpgradeEffect::ClusterWarhead => self.cluster = true,
+            UpgradeEffect::Homing => self.homing = true,
+            UpgradeEffect::Salvo { launchers } => {
+                self.launchers.extend((0..launchers).map(|_| 0));
+                self.salvo += launchers;
+            }
+            UpgradeEffect::Swallows { .. } => {}
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -55,6 +69,10 @@ pub struct Missile {
     pub age: u32,
     tower_x: f32,
     tower_y: f32,
+    /// Tower that fired the missile.
+    pub source: Option<u32>,
+    cluster: bool,
+    homing: bool,
 }
 
 pub fn create_missile_tower(
@@ -80,9 +98,13 @@ pub fn create_missile_tower(
     spawners.insert(
         entity,
         MissileSpawner {
-            left_reload_countdown: 0,
-            right_reload_countdown: 0,
-            reload_cost: (60.0 / config.common[MISSILE_INDEX].base_rate_of_fire).round() as u32,
+            reload_countdown: 0,
+            launchers: vec![0; config.missile.launchers as usize],
+            next_launcher: 0,
+            salvo: 1,
+            cluster: false,
+            homing: false,
+            reload_cost: reload_cost(config.common[MISSILE_INDEX].base_rate_of_fire),
             rotation: -PI / 2.0,
             rotation_speed: 0.0,
         },
@@ -90,25 +112,66 @@ pub fn create_missile_tower(
     entity
 }
 
+/// Ticks between salvos for a rate of fire in salvos per second.
+pub fn reload_cost(rate_of_fire: f32) -> u32 {
+    (60.0 / rate_of_fire).round() as u32
+}
+
+/// Sideways offset of a launcher from the center of its tower. Positive is to
+/// the right.
+fn launcher_offset(launcher: usize, launchers: usize) -> f32 {
+    (launcher as f32 - (launchers as f32 - 1.0) / 2.0) * MISSILE_WIDTH
+}
+
 fn spawn_missile(
     entity: u32,
-    target: u32,
-    tower: &Tower,
-    rotation: f32,
-    reloading_signum: f32,
+    missile: Missile,
+    offset: f32,
     missiles: &mut Map<u32, Missile>,
     mobs: &mut Map<u32, Mob>,
 ) {
-    let (tower_x, tower_y) = tile_center(tower.row, tower.col);
-
-    missiles.insert(entity, Missile::new(target, rotation, tower_x, tower_y));
     mobs.insert(
         entity,
         Mob::new(
-            tower_x + reloading_signum * rotation.sin() * MISSILE_WIDTH * 0.5,
-            tower_y - reloading_signum * rotation.cos() * MISSILE_WIDTH * 0.5,
+            missile.tower_x + missile.rotation.sin() * offset,
+            missile.tower_y - missile.rotation.cos() * offset,
         ),
     );
+    missiles.insert(entity, missile);
+}
+
+/// Number of missiles locked onto each enemy.
+fn locked_targets(missiles: &Map<u32, Missile>) -> Map<u32, u32> {
+    let mut locked = Map::default();
+    for missile in missiles.values() {
+        *locked.entry(missile.target).or_insert(0) += 1;
+    }
+    locked
+}
+
+/// Scatter a cluster warhead's sub-explosions evenly around a hit.
+fn scatter_cluster(
+    entity_ids: &mut EntityIds,
+    explosions: &mut Map<u32, Explosion>,
+    x: f32,
+    y: f32,
+    missile: &Missile,
+    config: &Config,
+) {
+    let count = config.missile.cluster_count;
+    let spread = config.missile.cluster_spread * f32::TILE_SIZE;
+    for i in 0..count {
+        let angle = missile.rotation + i as f32 * TAU / count as f32;
+        spawn_explosion(
+            entity_ids.next(),
+            explosions,
+            x + spread * angle.cos(),
+            y + spread * angle.sin(),
+            config.missile.cluster_radius * f32::TILE_SIZE,
+            missile.damage(config) * config.missile.cluster_damage,
+            missile.source,
+        );
+    }
 }
 
 fn fly_toward(
@@ -149,6 +212,7 @@ fn fly_toward(
 
 impl World {
     pub fn operate_missile_towers(&mut self) {
+        let mut locked = locked_targets(&self.core_state.missiles);
         for (entity, spawner) in &mut self.core_state.missile_spawners {
             if let Some(tower) = self.core_state.towers.get(entity) {
                 if tower.status != TowerStatus::Operational {
@@ -199,55 +263,68 @@ impl World {
                     &mut spawner.rotation_speed,
                     target_rotation,
                     target_d_rotation,
-                    TOWER_MAX_TURN_SPEED,
-                    TOWER_ROTATION_ACCEL,
+                    self.config.missile.tower_max_turn_speed,
+                    self.config.missile.tower_rotation_accel,
                     crate::ease::Domain::Radian { miss_adjust: 1.0 },
                 );
 
-                if spawner.right_reload_countdown > 0 {
-                    spawner.right_reload_countdown -= 1;
-                } else if spawner.left_reload_countdown <= 0 {
-                    if let Some((target, _, _)) = first_mob_in_range {
-                        let entity = self.core_state.entity_ids.next();
-                        spawn_missile(
-                            entity,
-                            target,
-                            tower,
-                            spawner.rotation,
-                            1.0,
-                            &mut self.core_state.missiles,
-                            &mut self.core_state.mobs,
-                        );
-                        spawn_smoke_trail(
-                            &mut self.core_state.entity_ids,
-                            &mut self.render_state.smoke_trails,
-                            entity,
-                        );
-                        spawner.right_reload_countdown = spawner.reload_cost;
-                    }
+                spawner.reload_countdown = spawner.reload_countdown.saturating_sub(1);
+                for countdown in &mut spawner.launchers {
+                    *countdown = countdown.saturating_sub(1);
+                }
+                if spawner.reload_countdown > 0 || spawner.launchers.is_empty() {
+                    continue;
                 }
+                if let Some((first_target, _, _)) = first_mob_in_range {
+                    for _ in 0..spawner.salvo {
+                        let launcher = spawner.next_launcher % spawner.launchers.len();
+                        spawner.next_launcher = (launcher + 1) % spawner.launchers.len();
+                        spawner.launchers[launcher] = spawner.reload_cost;
+
+                        let target = if spawner.homing {
+                            find_spread_target(
+                                tower_x,
+                                tower_y,
+                                tower.range,
+                                &locked,
+                                &self.core_state.walkers,
+                                &self.core_state.mobs,
+                            )
+                            .map(|(target, _, _)| target)
+                            .unwrap_or(first_target)
+                        } else {
+                            first_target
+                        };
+                        *locked.entry(target).or_insert(0) += 1;
 
-                if spawner.left_reload_countdown > 0 {
-                    spawner.left_reload_countdown -= 1;
-                } else if spawner.right_reload_countdown <= 0 {
-                    if let Some((target, _, _)) = first_mob_in_range {
-                        let entity = self.core_state.entity_ids.next();
-                        spawn_missile(
-                            entity,
+                        let missile_entity = self.core_state.entity_ids.next();
+                        let mut missile = Missile::new(
                             target,
-                            tower,
                             spawner.rotation,
-                            -1.0,
+                            tower_x,
+                            tower_y,
+                            &self.config.missile,
+                        );
+                        missile.source = Some(*entity);
+                        missile.cluster = spawner.cluster;
+                        missile.homing = spawner.homing;
+                        spawn_missile(
+                            missile_entity,
+                            missile,
+                            launcher_offset(launcher, spawner.launchers.len()),
                             &mut self.core_state.missiles,
                             &mut self.core_state.mobs,
                         );
+                        self.core_state
+                            .stats
+                            .record_shot(*entity, self.core_state.wave_spawner.current_wave());
                         spawn_smoke_trail(
                             &mut self.core_state.entity_ids,
                             &mut self.render_state.smoke_trails,
-                            entity,
+                            missile_entity,
                         );
-                        spawner.left_reload_countdown = spawner.reload_cost;
                     }
+                    spawner.reload_countdown = spawner.reload_cost;
                 }
             }
         }
@@ -258,6 +335,7 @@ impl World {
     /// Max speed is simulated with simple air resistance.
     pub fn fly_missiles(&mut self) {
         let mut trash = Vec::new();
+        let mut locked = locked_targets(&self.core_state.missiles);
         for (&entity, missile) in &mut self.core_state.missiles {
             missile.age += 1;
 
@@ -281,9 +359,20 @@ impl World {
                                 &mut self.core_state.explosions,
                                 missile_tip_x,
                                 missile_tip_y,
-                                1.2 * f32::TILE_SIZE,
+                                self.config.missile.explosion_radius * f32::TILE_SIZE,
                                 missile.damage(&self.config),
+                                missile.source,
                             );
+                            if missile.cluster {
+                                scatter_cluster(
+                                    &mut self.core_state.entity_ids,
+                                    &mut self.core_state.explosions,
+                                    missile_tip_x,
+                                    missile_tip_y,
+                                    missile,
+                                    &self.config,
+                                );
+                            }
                             trash.push(entity);
                             continue;
                         } else if distance_squared < THREAT_DISTANCE * THREAT_DISTANCE {
@@ -308,16 +397,30 @@ impl World {
                 };
                 // Find a new target.
                 // If there are enemies around, aim for the closest one.
-                if let Some((target, target_x, target_y)) = find_target(
-                    x,
-                    y,
-                    f32::INFINITY,
-                    Targeting::Close,
-                    &self.core_state.walkers,
-                    &self.core_state.mobs,
-                    &self.level_state,
-                ) {
+                // Homing missiles prefer enemies that nothing is locked onto.
+                let new_target = if missile.homing {
+                    find_spread_target(
+                        x,
+                        y,
+                        f32::INFINITY,
+                        &locked,
+                        &self.core_state.walkers,
+                        &self.core_state.mobs,
+                    )
+                } else {
+                    find_target(
+                        x,
+                        y,
+                        f32::INFINITY,
+                        Targeting::Close,
+                        &self.core_state.walkers,
+                        &self.core_state.mobs,
+                        &self.level_state,
+                    )
+                };
+                if let Some((target, target_x, target_y)) = new_target {
                     missile.target = target;
+                    *locked.entry(target).or_insert(0) += 1;
                     if let Some(missile_mob) = self.core_state.mobs.get_mut(&entity) {
                         fly_toward(target_x, target_y, missile, missile_mob, 0.0);
                     }
@@ -369,34 +472,22 @@ impl World {
                     self.config.common[MISSILE_INDEX].color,
                 );
 
-                let left_peek = 3.0
-                    * ease_peek(spawner.left_reload_countdown as f32 / spawner.reload_cost as f32);
-                let right_peek = 3.0
-                    * ease_peek(spawner.right_reload_countdown as f32 / spawner.reload_cost as f32);
-
                 let recoil_amount = 2.0
-                    * spawner
-                        .left_reload_countdown
-                        .max(spawner.right_reload_countdown) as f32
+                    * spawner.launchers.iter().copied().max().unwrap_or(0) as f32
                     / spawner.reload_cost as f32;
 
-                self.render_state.sprite_data.push(
-                    SpriteType::Missile as u8,
-                    tower_x + MISSILE_WIDTH * 0.5 * sin + (right_peek - recoil_amount) * cos,
-                    tower_y - MISSILE_WIDTH * 0.5 * cos + (right_peek - recoil_amount) * sin,
-                    rotation,
-                    1.0,
-                    0x000000,
-                );
-
-                self.render_state.sprite_data.push(
-                    SpriteType::Missile as u8,
-                    tower_x - MISSILE_WIDTH * 0.5 * sin + (left_peek - recoil_amount) * cos,
-                    tower_y + MISSILE_WIDTH * 0.5 * cos + (left_peek - recoil_amount) * sin,
-                    rotation,
-                    1.0,
-                    0x000000,
-                );
+                for (launcher, &countdown) in spawner.launchers.iter().enumerate() {
+                    let offset = launcher_offset(launcher, spawner.launchers.len());
+                    let peek = 3.0 * ease_peek(countdown as f32 / spawner.reload_cost as f32);
+                    self.render_state.sprite_data.push(
+                        SpriteType::Missile as u8,
+                        tower_x + offset * sin + (peek - recoil_amount) * cos,
+                        tower_y - offset * cos + (peek - recoil_amount) * sin,
+                        rotation,
+                        1.0,
+                        0x000000,
+                    );
+                }
 
                 self.render_state.sprite_data.push(
                     SpriteType::MissileTower as u8,
@@ -412,19 +503,28 @@ impl World {
 }
 
 impl Missile {
-    fn new(target: u32, rotation: f32, tower_x: f32, tower_y: f32) -> Missile {
+    fn new(
+        target: u32,
+        rotation: f32,
+        tower_x: f32,
+        tower_y: f32,
+        config: &MissileConfig,
+    ) -> Missile {
         Missile {
             target,
             rotation,
             rotation_speed: 0.0,
-            rotation_acceleration: ROTATION_ACCEL,
-            max_turn_speed: MAX_TURN_SPEED,
-            max_speed: MAX_SPEED,
+            rotation_acceleration: config.rotation_accel,
+            max_turn_speed: config.max_turn_speed,
+            max_speed: config.max_speed,
             speed: 0.0,
-            acceleration: ACCELERATION,
+            acceleration: config.acceleration,
             age: 0,
             tower_x,
             tower_y,
+            source: None,
+            cluster: false,
+            homing: false,
         }
     }
 
@@ -0,0 +1,187 @@
+//! Compact encoding for stored copies of the core state.
+//!
+//! Consecutive checkpoints have mostly the same bytes, so we store most
+//! snapshots as a delta against the previous snapshot's bytes. Collections
+//! grow and shrink between checkpoints, which shifts everything serialized
+//! after them, so the delta is made of copies from anywhere in the base plus
+//! literal bytes, rather than a byte by byte comparison.
+
+use fnv::FnvHashMap;
+
+use crate::world::CoreState;
+
+/// Shortest run of matching bytes that is worth a copy.
+const MIN_COPY: usize = 8;
+
+const COPY: u8 = 0;
+const INSERT: u8 = 1;
+
+pub fn serialize(core_state: &CoreState) -> Vec<u8> {
+    bincode::serialize(core_state).unwrap_or_default()
+}
+
+pub fn deserialize(bytes: &[u8]) -> Option<CoreState> {
+    bincode::deserialize(bytes).ok()
+}
+
+fn push_u32(out: &mut Vec<u8>, n: usize) {
+    out.extend_from_slice(&(n as u32).to_le_bytes());
+}
+
+fn read_u32(bytes: &[u8], i: &mut usize) -> Option<usize> {
+    let chunk = bytes.get(*i..*i + 4)?;
+    *i += 4;
+    Some(u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]) as usize)
+}
+
+fn block_key(bytes: &[u8], i: usize) -> u64 {
+    let mut block = [0; MIN_COPY];
+    block.copy_from_slice(&bytes[i..i + MIN_COPY]);
+    u64::from_le_bytes(block)
+}
+
+/// Length of the run of matching bytes starting at `base[j]` and `new[i]`.
+fn match_len(base: &[u8], j: usize, new: &[u8], i: usize) -> usize {
+    base[j..]
+        .iter()
+        .zip(&new[i..])
+        .take_while(|(a, b)| a == b)
+        .count()
+}
+
+fn push_insert(out: &mut Vec<u8>, literal: &[u8]) {
+    if !literal.is_empty() {
+        out.push(INSERT);
+        push_u32(out, literal.len());
+        out.extend_from_slice(literal);
+    }
+}
+
+/// Encode `new` relative to `base`.
+///
+/// The format is the length of `new`, followed by operations that build it
+/// up in order: either a copy of a range of `base`, given as an offset and a
+/// length, or a count of literal bytes followed by those bytes.
+pub fn encode_delta(base: &[u8], new: &[u8]) -> Vec<u8> {
+    // Where each block of bytes first appears in the base
+    let mut blocks = FnvHashMap::default();
+    for j in 0..(base.len() + 1).saturating_sub(MIN_COPY) {
+        blocks.entry(block_key(base, j)).or_insert(j);
+    }
+
+    let mut out = Vec::new();
+    push_u32(&mut out, new.len());
+
+    let mut literal_start = 0;
+    // The base offset right after the last copy. Unchanged bytes usually
+    // carry on from there, past any bytes that were overwritten in place, so
+    // try that before looking anywhere else.
+    let mut next_copy = 0;
+    let mut i = 0;
+    while i + MIN_COPY <= new.len() {
+        let expected = (next_copy + i - literal_start).min(base.len());
+        let continued = match_len(base, expected, new, i);
+        let (offset, len) = if continued >= MIN_COPY {
+            (expected, continued)
+        } else {
+            match blocks.get(&block_key(new, i)) {
+                Some(&j) => (j, match_len(base, j, new, i)),
+                None => (0, 0),
+            }
+        };
+        if len < MIN_COPY {
+            i += 1;
+            continue;
+        }
+        push_insert(&mut out, &new[literal_start..i]);
+        out.push(COPY);
+        push_u32(&mut out, offset);
+        push_u32(&mut out, len);
+        i += len;
+        literal_start = i;
+        next_copy = offset + len;
+    }
+    push_insert(&mut out, &new[literal_start..]);
+
+    out
+}
+
+/// Inverse of encode_delta. Returns None if the delta is malformed.
+pub fn apply_delta(base: &[u8], delta: &[u8]) -> Option<Vec<u8>> {
+    let mut i = 0;
+    let len = read_u32(delta, &mut i)?;
+    let mut out = Vec::with_capacity(len);
+
+    while out.len() < len {
+        let op = *delta.get(i)?;
+        i += 1;
+        match op {
+            COPY => {
+                let offset = read_u32(delta, &mut i)?;
+                let copy_len = read_u32(delta, &mut i)?;
+                out.extend_from_slice(base.get(offset..offset + copy_len)?);
+            }
+            INSERT => {
+                let literal_len = read_u32(delta, &mut i)?;
+                out.extend_from_slice(delta.get(i..i + literal_len)?);
+                i += literal_len;
+            }
+            _ => return None,
+        }
+    }
+
+    if out.len() == len {
+        Some(out)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn delta_round_trips(
+            base in proptest::collection::vec(any::<u8>(), 0..512),
+            new in proptest::collection::vec(any::<u8>(), 0..512),
+        ) {
+            let delta = encode_delta(&base, &new);
+            prop_assert_eq!(apply_delta(&base, &delta), Some(new));
+        }
+
+        /// Small edits should produce small deltas.
+        #[test]
+        fn similar_bytes_make_small_deltas(
+            base in proptest::collection::vec(any::<u8>(), 256..512),
+            edit_index in 0_usize..256,
+            edit in 1_u8..=255,
+        ) {
+            let mut new = base.clone();
+            new[edit_index] ^= edit;
+            let delta = encode_delta(&base, &new);
+            prop_assert!(delta.len() < 32);
+            prop_assert_eq!(apply_delta(&base, &delta), Some(new));
+        }
+
+        /// Growing a collection shifts every byte after it, which shouldn't
+        /// make the delta any bigger than the bytes that were added.
+        #[test]
+        fn inserted_bytes_make_small_deltas(
+            base in proptest::collection::vec(any::<u8>(), 256..4096),
+            insert_index in any::<prop::sample::Index>(),
+            inserted in proptest::collection::vec(any::<u8>(), 1..64),
+        ) {
+            let mut new = base.clone();
+            let insert_index = insert_index.index(base.len());
+            new.splice(insert_index..insert_index, inserted.iter().copied());
+            let delta = encode_delta(&base, &new);
+            prop_assert!(delta.len() < inserted.len() + 64,
+                "Delta was {} bytes for {} inserted bytes", delta.len(), inserted.len());
+            prop_assert_eq!(apply_delta(&base, &delta), Some(new));
+        }
+    }
+}
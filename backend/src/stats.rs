@@ -0,0 +1,102 @@
+//! Combat statistics, so that players can see which towers pull their weight.
+
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+use crate::world::{Map, World};
+
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct CombatStats {
+    pub damage_dealt: f32,
+    pub kills: u32,
+    pub shots_fired: u32,
+    /// Damage beyond what was needed to kill, including damage to enemies
+    /// that were already dead.
+    pub overkill: f32,
+}
+
+impl CombatStats {
+    fn add(&mut self, other: &CombatStats) {
+        self.damage_dealt += other.damage_dealt;
+        self.kills += other.kills;
+        self.shots_fired += other.shots_fired;
+        self.overkill += other.overkill;
+    }
+
+    /// Flatten for js, in the same order as the fields.
+    fn to_vec(&self) -> Vec<f32> {
+        vec![
+            self.damage_dealt,
+            self.kills as f32,
+            self.shots_fired as f32,
+            self.overkill,
+        ]
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct Stats {
+    /// Stats for each tower entity.
+    #[serde(with = "indexmap::serde_seq")]
+    pub towers: Map<u32, CombatStats>,
+    /// Stats for all towers combined, for each wave.
+    pub waves: Vec<CombatStats>,
+}
+
+impl Stats {
+    fn for_wave(&mut self, wave: usize) -> &mut CombatStats {
+        if self.waves.len() <= wave {
+            self.waves.resize_with(wave + 1, Default::default);
+        }
+        &mut self.waves[wave]
+    }
+
+    fn record(&mut self, source: u32, wave: usize, stats: &CombatStats) {
+        self.towers.entry(source).or_default().add(stats);
+        self.for_wave(wave).add(stats);
+    }
+
+    pub fn record_shot(&mut self, source: u32, wave: usize) {
+        let shot = CombatStats {
+            shots_fired: 1,
+            ..Default::default()
+        };
+        self.record(source, wave, &shot);
+    }
+
+    pub fn record_hit(&mut self, source: u32, wave: usize, dealt: f32, overkill: f32, kill: bool) {
+        let hit = CombatStats {
+            damage_dealt: dealt,
+            kills: kill as u32,
+            shots_fired: 0,
+            overkill,
+        };
+        self.record(source, wave, &hit);
+    }
+}
+
+#[wasm_bindgen]
+impl World {
+    /// Damage dealt, kills, shots fired and overkill for a tower entity.
+    pub fn query_tower_stats(&self, entity: u32) -> Vec<f32> {
+        self.core_state
+            .stats
+            .towers
+            .get(&entity)
+            .cloned()
+            .unwrap_or_default()
+            .to_vec()
+    }
+
+    /// Stats for all towers during a wave, in the same order as
+    /// query_tower_stats.
+    pub fn query_wave_stats(&self, wave: usize) -> Vec<f32> {
+        self.core_state
+            .stats
+            .waves
+            .get(wave)
+            .cloned()
+            .unwrap_or_default()
+            .to_vec()
+    }
+}
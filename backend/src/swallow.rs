@@ -14,18 +14,26 @@ use serde::{Deserialize, Serialize};
 use crate::{
     build::BuildOrder,
     collision::circle_line_intersection,
-    config::Config,
+    config::{Config, SwallowConfig},
     ease::ease_to_x_geometric,
     graphics::{SpriteData, SpriteType},
+    health::{Damage, DamageKind},
     map::{tile_center, Constants},
     mob::{closest_walker, Mob},
-    targeting::{find_target, Targeting, Threat},
+    targeting::{find_spread_target, Threat},
     tower::{create_tower, Tower, TowerStatus, SWALLOW_INDEX},
     walker::STANDARD_ENEMY_RADIUS,
+    waves::TICKS_PER_SECOND,
     world::{EntityIds, Map, World},
 };
 
-const SPEED: f32 = 5.2;
+/// A swallow's rate of fire is against an enemy this far from the tower,
+/// counting only the flight there and back.
+const REFERENCE_DISTANCE: f32 = 3.0 * f32::TILE_SIZE;
+/// Cap on how much faster swallows fly when enemies are close.
+const MAX_SPEEDUP: f32 = 2.0;
+/// Turning at the reference speed. Turning scales with speed so that
+/// swallows keep the same flight paths at any speed.
 const ROTATION_ACCEL: f32 = 0.05;
 const MAX_TURN_SPEED: f32 = 0.25;
 const SWALLOW_RADIUS: f32 = f32::TILE_SIZE * 0.3;
@@ -87,14 +95,20 @@ pub enum Target {
 }
 
 impl Swallow {
-    fn new(rotation: f32, tower_x: f32, tower_y: f32, home_tower: u32) -> Swallow {
+    fn new(
+        rotation: f32,
+        tower_x: f32,
+        tower_y: f32,
+        home_tower: u32,
+        rate_of_fire: f32,
+    ) -> Swallow {
         Swallow {
             target: Target::None,
             rotation,
             rotation_speed: 0.0,
             rotation_accel: ROTATION_ACCEL,
             max_turn_speed: MAX_TURN_SPEED,
-            speed: SPEED,
+            speed: reference_speed(rate_of_fire),
             vanishing_x: tower_x,
             vanishing_y: tower_y,
             home_tower,
@@ -103,6 +117,16 @@ impl Swallow {
         }
     }
 
+    /// Fly faster the closer the closest enemy is to the swallow's current
+    /// tower.
+    fn set_cadence(&mut self, rate_of_fire: f32, closest_distance_squared: f32) {
+        let speedup =
+            (REFERENCE_DISTANCE / closest_distance_squared.sqrt()).clamp(1.0, MAX_SPEEDUP);
+        self.speed = reference_speed(rate_of_fire) * speedup;
+        self.max_turn_speed = MAX_TURN_SPEED * speedup;
+        self.rotation_accel = ROTATION_ACCEL * speedup;
+    }
+
     pub fn dump(&self, id: &u32, data: &mut SpriteData, mobs: &Map<u32, Mob>, frame_fudge: f32) {
         if let Some(mob) = mobs.get(id) {
             data.push(
@@ -117,6 +141,13 @@ impl Swallow {
     }
 }
 
+/// Speed, in pixels per tick, that gives the rate of fire (attacks per
+/// second) against an enemy at the reference distance.
+fn reference_speed(rate_of_fire: f32) -> f32 {
+    let round_trip = 2.0 * REFERENCE_DISTANCE;
+    rate_of_fire * round_trip / TICKS_PER_SECOND as f32
+}
+
 impl SwallowAfterImage {
     pub fn dump(&self, data: &mut SpriteData, frame_fudge: f32) {
         data.push(
@@ -169,15 +200,10 @@ pub fn create_swallow_tower(
     towers: &mut Map<u32, Tower>,
     towers_by_pos: &mut Map<(usize, usize), u32>,
     swallow_targeters: &mut Map<u32, SwallowTargeter>,
-    swallows: &mut Map<u32, Swallow>,
-    mobs: &mut Map<u32, Mob>,
     build_orders: &mut VecDeque<BuildOrder>,
     config: &Config,
 ) -> u32 {
     let tower_entity = entities.next();
-    let swallow_entity = entities.next();
-
-    let (x, y) = tile_center(row, col);
 
     create_tower(
         row,
@@ -196,16 +222,76 @@ pub fn create_swallow_tower(
             closest_distance_squared: f32::INFINITY,
             closest_x: 0.0,
             closest_y: 0.0,
-            home_swallow_entities: vec![swallow_entity],
+            home_swallow_entities: Vec::new(),
         },
     );
 
-    swallows.insert(swallow_entity, Swallow::new(-PI / 2.0, x, y, tower_entity));
-    mobs.insert(swallow_entity, Mob::new(x, y));
-
     tower_entity
 }
 
+/// Whether two swallows' towers are close enough for the swallows to flock.
+fn are_flockmates(tower_a: u32, tower_b: u32, towers: &Map<u32, Tower>) -> bool {
+    if tower_a == tower_b {
+        return true;
+    }
+    match (towers.get(&tower_a), towers.get(&tower_b)) {
+        (Some(a), Some(b)) => {
+            (a.row as isize - b.row as isize).abs() <= 1
+                && (a.col as isize - b.col as isize).abs() <= 1
+        }
+        _ => false,
+    }
+}
+
+/// Separation and alignment for swallows that are chasing enemies, as a
+/// vector to add to each swallow's unit heading.
+fn flock_steering(
+    swallows: &Map<u32, Swallow>,
+    mobs: &Map<u32, Mob>,
+    towers: &Map<u32, Tower>,
+    config: &SwallowConfig,
+) -> Map<u32, (f32, f32)> {
+    let radius = config.flock_radius * f32::TILE_SIZE;
+    let chasing: Vec<(u32, &Swallow, &Mob)> = swallows
+        .iter()
+        .filter(|(_, swallow)| matches!(swallow.target, Target::Enemy(_)))
+        .filter_map(|(&entity, swallow)| mobs.get(&entity).map(|mob| (entity, swallow, mob)))
+        .collect();
+
+    let mut steering = Map::default();
+    for &(entity, swallow, mob) in &chasing {
+        let mut separation = (0.0, 0.0);
+        let mut alignment = (0.0, 0.0);
+        for &(other_entity, other, other_mob) in &chasing {
+            if other_entity == entity
+                || !are_flockmates(swallow.home_tower, other.home_tower, towers)
+            {
+                continue;
+            }
+            let dx = mob.x - other_mob.x;
+            let dy = mob.y - other_mob.y;
+            let distance = (dx * dx + dy * dy).sqrt();
+            // Swallows in the exact same spot have no direction to separate in
+            if distance >= radius || distance == 0.0 {
+                continue;
+            }
+            let weight = 1.0 - distance / radius;
+            separation.0 += weight * dx / distance;
+            separation.1 += weight * dy / distance;
+            alignment.0 += weight * other.rotation.cos();
+            alignment.1 += weight * other.rotation.sin();
+        }
+        steering.insert(
+            entity,
+            (
+                config.separation * separation.0 + config.alignment * alignment.0,
+                config.separation * separation.1 + config.alignment * alignment.1,
+            ),
+        );
+    }
+    steering
+}
+
 fn create_swallow_after_image(
     entity: u32,
     swallow_mob: &Mob,
@@ -248,8 +334,62 @@ fn should_migrate_home(
 }
 
 impl World {
+    /// Give a swallow tower more swallows. They start at the tower, fanned
+    /// out so that they don't sit on top of each other.
+    pub fn add_swallows(&mut self, tower_entity: u32, count: u32) {
+        let (x, y, rate_of_fire) = match self.core_state.towers.get(&tower_entity) {
+            Some(tower) => {
+                let (x, y) = tile_center(tower.row, tower.col);
+                (x, y, tower.rate_of_fire)
+            }
+            None => return,
+        };
+        if let Some(targeter) = self.core_state.swallow_targeters.get_mut(&tower_entity) {
+            for _ in 0..count {
+                let swallow_entity = self.core_state.entity_ids.next();
+                let i = targeter.home_swallow_entities.len();
+                let rotation = -PI / 2.0 + i as f32 * PI / 4.0;
+                self.core_state.swallows.insert(
+                    swallow_entity,
+                    Swallow::new(rotation, x, y, tower_entity, rate_of_fire),
+                );
+                self.core_state.mobs.insert(swallow_entity, Mob::new(x, y));
+                targeter.home_swallow_entities.push(swallow_entity);
+            }
+        }
+    }
+
     pub fn fly_swallows(&mut self) {
+        let base_rate_of_fire = self.config.common[SWALLOW_INDEX].base_rate_of_fire;
+        let steering = flock_steering(
+            &self.core_state.swallows,
+            &self.core_state.mobs,
+            &self.core_state.towers,
+            &self.config.swallow,
+        );
+        // Number of swallows chasing each enemy
+        let mut claims: Map<u32, u32> = Map::default();
+        for swallow in self.core_state.swallows.values() {
+            if let Target::Enemy(target) = swallow.target {
+                *claims.entry(target).or_insert(0) += 1;
+            }
+        }
+
         for (&entity, swallow) in &mut self.core_state.swallows {
+            let closest_distance_squared = self
+                .core_state
+                .swallow_targeters
+                .get(&swallow.curr_tower)
+                .map(|targeter| targeter.closest_distance_squared)
+                .unwrap_or(f32::INFINITY);
+            let rate_of_fire = self
+                .core_state
+                .towers
+                .get(&swallow.home_tower)
+                .map(|tower| tower.rate_of_fire)
+                .unwrap_or(base_rate_of_fire);
+            swallow.set_cadence(rate_of_fire, closest_distance_squared);
+
             if let Some(swallow_mob) = self.core_state.mobs.get(&entity) {
                 if let Some(home_tower) = self.core_state.towers.get(&swallow.home_tower) {
                     if home_tower.status != TowerStatus::Operational {
@@ -279,16 +419,20 @@ impl World {
                                     swallow,
                                     swallow_mob,
                                 );
-                            } else if let Some((target, target_x, target_y)) = find_target(
+                            } else if let Some((target, target_x, target_y)) = find_spread_target(
                                 swallow_mob.x,
                                 swallow_mob.y,
                                 home_tower.range,
-                                Targeting::Close,
+                                &claims,
                                 &self.core_state.walkers,
                                 &self.core_state.mobs,
-                                &self.level_state,
                             ) {
                                 swallow.target = Target::Enemy(target);
+                                *claims.entry(target).or_insert(0) += 1;
+                                self.core_state.stats.record_shot(
+                                    swallow.home_tower,
+                                    self.core_state.wave_spawner.current_wave(),
+                                );
                                 swallow.rotation =
                                     f32::atan2(target_y - swallow_mob.y, target_x - swallow_mob.x);
                                 swallow.vanishing_x = swallow_mob.x;
@@ -367,10 +511,12 @@ impl World {
                                 }
 
                                 // Deal damage
-                                if let Some(health) = self.core_state.health.get_mut(&target) {
-                                    health.curr_health -=
-                                        self.config.common[SWALLOW_INDEX].base_damage;
-                                }
+                                self.core_state.damage.push(Damage {
+                                    target,
+                                    amount: self.config.common[SWALLOW_INDEX].base_damage,
+                                    kind: DamageKind::Physical,
+                                    source: Some(swallow.home_tower),
+                                });
 
                                 // Alert the target
                                 self.core_state.threats.insert(target, Threat {});
@@ -441,6 +587,16 @@ impl World {
                             let rotation =
                                 f32::atan2(goal_y - swallow_mob.y, goal_x - swallow_mob.x);
 
+                            // Flock with nearby swallows, less so when about
+                            // to hit the target
+                            let (steer_x, steer_y) =
+                                steering.get(&entity).copied().unwrap_or((0.0, 0.0));
+                            let fade = (distance_squared.sqrt() / f32::TILE_SIZE).min(1.0);
+                            let rotation = f32::atan2(
+                                rotation.sin() + fade * steer_y,
+                                rotation.cos() + fade * steer_x,
+                            );
+
                             ease_to_x_geometric(
                                 &mut swallow.rotation,
                                 &mut swallow.rotation_speed,
@@ -0,0 +1,240 @@
+//! Bonuses that towers get from their neighbors, declared in the config. This
+//! generalizes the way factories speed up construction next to them.
+
+use wasm_bindgen::prelude::*;
+
+use crate::{
+    build::tower_is_done,
+    config::{Config, Synergy, SynergyEffect},
+    map::{Constants, Terrain},
+    missile::reload_cost,
+    tower::Tower,
+    world::{Map, World},
+};
+
+/// Orthogonal neighbors of a tile. Neighbors off the top or left of the map
+/// wrap around to positions that never have towers.
+fn neighbors(row: usize, col: usize) -> [(usize, usize); 4] {
+    [
+        (row.wrapping_sub(1), col),
+        (row + 1, col),
+        (row, col.wrapping_sub(1)),
+        (row, col + 1),
+    ]
+}
+
+/// Whether a tower of one type gets a synergy's bonus from a neighbor of
+/// another type.
+fn applies(synergy: &Synergy, config: &Config, type_index: usize, neighbor_index: usize) -> bool {
+    let tower_matches = match &synergy.tower {
+        Some(name) => config.tower_index(name) == Some(type_index),
+        None => true,
+    };
+    tower_matches && config.tower_index(&synergy.neighbor) == Some(neighbor_index)
+}
+
+/// Bonuses that a tower gets from its finished neighbors, as fractions of its
+/// base stats.
+#[derive(Default, Debug, PartialEq)]
+struct Bonuses {
+    range: f32,
+    rate_of_fire: f32,
+    burning: f32,
+}
+
+fn neighbor_bonuses(
+    config: &Config,
+    towers: &Map<u32, Tower>,
+    towers_by_pos: &Map<(usize, usize), u32>,
+    tower: &Tower,
+) -> Bonuses {
+    let mut bonuses = Bonuses::default();
+    for pos in &neighbors(tower.row, tower.col) {
+        let neighbor = match towers_by_pos
+            .get(pos)
+            .and_then(|neighbor_entity| towers.get(neighbor_entity))
+        {
+            Some(neighbor) if tower_is_done(neighbor) => neighbor,
+            _ => continue,
+        };
+        for synergy in &config.synergies {
+            if !applies(synergy, config, tower.type_index, neighbor.type_index) {
+                continue;
+            }
+            match synergy.effect {
+                SynergyEffect::Range { bonus } => bonuses.range += bonus,
+                SynergyEffect::RateOfFire { bonus } => bonuses.rate_of_fire += bonus,
+                SynergyEffect::Burning { bonus } => bonuses.burning += bonus,
+            }
+        }
+    }
+    bonuses
+}
+
+impl World {
+    /// Recompute the stats of every tower from its finished neighbors and the
+    /// ground it stands on. This only needs to happen when a tower is
+    /// finished or destroyed.
+    pub fn refresh_synergies(&mut self) {
+        let towers = &self.core_state.towers;
+        let mut stats = Vec::with_capacity(towers.len());
+        for (&entity, tower) in towers {
+            let Bonuses {
+                mut range,
+                rate_of_fire,
+                burning,
+            } = neighbor_bonuses(&self.config, towers, &self.core_state.towers_by_pos, tower);
+            if self.terrain_at(tower.row, tower.col) == Terrain::HighGround {
+                range += self.config.terrain.high_ground_range;
+            }
+            let base = self.config.get_common(tower.type_index);
+            stats.push((
+                entity,
+                base.base_range * f32::TILE_SIZE * (1.0 + range),
+                base.base_rate_of_fire * (1.0 + rate_of_fire),
+                1.0 + burning,
+            ));
+        }
+
+        for (entity, range, rate_of_fire, burning) in stats {
+            if let Some(tower) = self.core_state.towers.get_mut(&entity) {
+                tower.range = range;
+                tower.rate_of_fire = rate_of_fire;
+                tower.burning = burning;
+            }
+            if let Some(spawner) = self.core_state.missile_spawners.get_mut(&entity) {
+                spawner.reload_cost = reload_cost(rate_of_fire);
+            }
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl World {
+    /// Find the synergies that a tower placed here would take part in, either
+    /// by getting a bonus or by giving one to a neighbor. Unlike the actual
+    /// bonuses, queued neighbors count, since they will be built eventually.
+    /// Returns how many synergies there are. Their indices in the config are
+    /// in synergy_preview.
+    pub fn preview_synergies(&mut self, row: usize, col: usize, tower_index: usize) -> usize {
+        let towers = &self.core_state.towers;
+        let preview = &mut self.render_state.synergy_preview;
+        preview.clear();
+        for pos in &neighbors(row, col) {
+            let neighbor = match self
+                .core_state
+                .towers_by_pos
+                .get(pos)
+                .and_then(|entity| towers.get(entity))
+            {
+                Some(neighbor) => neighbor,
+                None => continue,
+            };
+            for (i, synergy) in self.config.synergies.iter().enumerate() {
+                let active = applies(synergy, &self.config, tower_index, neighbor.type_index)
+                    || applies(synergy, &self.config, neighbor.type_index, tower_index);
+                if active && !preview.contains(&(i as u32)) {
+                    preview.push(i as u32);
+                }
+            }
+        }
+        preview.len()
+    }
+
+    pub fn synergy_preview(&self) -> *const u32 {
+        self.render_state.synergy_preview.as_ptr()
+    }
+
+    pub fn query_synergy_description(&self, synergy_index: usize) -> String {
+        self.config
+            .synergies
+            .get(synergy_index)
+            .map(|synergy| synergy.description.clone())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::tower::TowerStatus;
+
+    const FALCON: usize = 0;
+    const MISSILE: usize = 1;
+
+    fn config() -> Config {
+        toml::from_str(
+            r#"
+            waves = []
+            enemies = []
+
+            [[common]]
+            name = "Falcon"
+            base_damage = 1.0
+            base_rate_of_fire = 1.0
+            base_range = 3.0
+            cost = 5.0
+            description = ""
+            flavor = ""
+            color = 0
+
+            [[common]]
+            name = "Missile"
+            base_damage = 1.0
+            base_rate_of_fire = 1.0
+            base_range = 4.0
+            cost = 5.0
+            description = ""
+            flavor = ""
+            color = 0
+
+            [[synergies]]
+            tower = "Missile"
+            neighbor = "Falcon"
+            effect = "Range"
+            bonus = 0.25
+            "#,
+        )
+        .unwrap()
+    }
+
+    fn tower(row: usize, col: usize, type_index: usize) -> Tower {
+        Tower {
+            row,
+            col,
+            range: 0.0,
+            rate_of_fire: 0.0,
+            burning: 1.0,
+            type_index,
+            status: TowerStatus::Operational,
+            upgrades: 0,
+        }
+    }
+
+    #[test]
+    fn bonuses_go_away_with_the_neighbor() {
+        let config = config();
+        let mut towers = Map::default();
+        let mut towers_by_pos = Map::default();
+        for (entity, tower) in [(1, tower(4, 4, MISSILE)), (2, tower(4, 5, FALCON))] {
+            towers_by_pos.insert((tower.row, tower.col), entity);
+            towers.insert(entity, tower);
+        }
+
+        let missile = &towers[&1];
+        let bonuses = neighbor_bonuses(&config, &towers, &towers_by_pos, missile);
+        assert_eq!(bonuses.range, 0.25);
+        // Synergies only go one way
+        let falcon = &towers[&2];
+        let bonuses = neighbor_bonuses(&config, &towers, &towers_by_pos, falcon);
+        assert_eq!(bonuses, Bonuses::default());
+
+        // Destroying the falcon takes the bonus with it
+        let falcon = towers.remove(&2).unwrap();
+        towers_by_pos.remove(&(falcon.row, falcon.col));
+        let missile = &towers[&1];
+        let bonuses = neighbor_bonuses(&config, &towers, &towers_by_pos, missile);
+        assert_eq!(bonuses, Bonuses::default());
+    }
+}
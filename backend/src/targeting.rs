@@ -54,6 +54,37 @@ pub fn find_target(
     }
 }
 
+/// Like targeting the closest enemy, except that enemies with more claims
+/// (swallows chasing them, missiles locked onto them) are only picked if every
+/// enemy in range has at least as many claims.
+/// Ties go to the enemy that comes first in iteration order, which keeps this
+/// deterministic.
+pub fn find_spread_target(
+    x: f32,
+    y: f32,
+    range: f32,
+    claims: &Map<u32, u32>,
+    walkers: &Map<u32, Walker>,
+    mobs: &Map<u32, Mob>,
+) -> Option<(u32, f32, f32)> {
+    walkers
+        .keys()
+        .filter_map(|entity| mobs.get(entity).map(|mob| (*entity, mob)))
+        .map(|(entity, mob)| {
+            let dx = mob.x - x;
+            let dy = mob.y - y;
+            (entity, mob, dx * dx + dy * dy)
+        })
+        .filter(|(_, _, distance_squared)| *distance_squared < range * range)
+        .min_by_key(|(entity, _, distance_squared)| {
+            (
+                claims.get(entity).copied().unwrap_or(0),
+                FloatOrd(*distance_squared),
+            )
+        })
+        .map(|(entity, mob, _)| (entity, mob.x, mob.y))
+}
+
 /// Towers that target mobs can alert the mobs by adding threat components to them.
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Threat {}
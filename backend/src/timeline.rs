@@ -0,0 +1,553 @@
+//! A branching tree of checkpoints that the player can travel between.
+//!
+//! Only some checkpoints store a snapshot of the core state. The rest are
+//! rebuilt by restoring an ancestor and replaying the player's commands on top
+//! of it, which works because the simulation is deterministic.
+//!
+//! Snapshots are delta encoded (see snapshot.rs) and have a memory budget.
+//! Once it is exceeded, the oldest checkpoints are forgotten.
+
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+use crate::{
+    factory::FactoryMode,
+    snapshot::{apply_delta, deserialize, encode_delta, serialize},
+    world::{CoreState, Map, World},
+};
+
+/// Every nth checkpoint down a branch stores a snapshot.
+const KEYFRAME_INTERVAL: usize = 4;
+
+/// Longest chain of deltas before we store a whole snapshot again. This
+/// bounds the work needed to decode a snapshot.
+const MAX_DELTA_CHAIN: usize = 8;
+
+/// Restoring within this many ticks of a checkpoint goes back to the one
+/// before it instead. Otherwise it would be nigh impossible to go back more
+/// than one checkpoint, since restoring to the start of a wave immediately
+/// checkpoints again.
+const RESTORE_GRACE_TICKS: u32 = 3 * 60;
+
+/// A player input that changes the core state. Replaying the commands from a
+/// checkpoint reproduces everything that happened after it.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub enum Command {
+    BuildTower {
+        row: usize,
+        col: usize,
+        tower_index: usize,
+    },
+    CancelConstruction {
+        row: usize,
+        col: usize,
+    },
+    SendNextWave,
+    QueueUpgrade {
+        row: usize,
+        col: usize,
+        upgrade_index: usize,
+    },
+    SetFactoryMode {
+        row: usize,
+        col: usize,
+        mode: FactoryMode,
+    },
+    MoveBuildOrderToFront {
+        index: usize,
+    },
+    MoveBuildOrderToBack {
+        index: usize,
+    },
+    SwapBuildOrders {
+        a: usize,
+        b: usize,
+    },
+    ToggleBuildOrderPaused {
+        index: usize,
+    },
+}
+
+/// Serialized core state.
+struct Snapshot {
+    /// If present, the bytes are a delta against this checkpoint's snapshot.
+    base: Option<u32>,
+    /// Number of deltas that need to be applied to decode this snapshot.
+    chain: usize,
+    bytes: Vec<u8>,
+}
+
+pub struct Checkpoint {
+    /// None for the oldest checkpoints.
+    parent: Option<u32>,
+    depth: usize,
+    /// Index of the next wave to spawn.
+    wave: usize,
+    tick: u32,
+    /// None if the state has to be rebuilt from an ancestor.
+    snapshot: Option<Snapshot>,
+    /// Commands issued between the parent checkpoint and this one, along
+    /// with the tick they were issued on.
+    inputs: Vec<(u32, Command)>,
+}
+
+pub struct Timeline {
+    /// Checkpoints by id, from oldest to newest.
+    checkpoints: Map<u32, Checkpoint>,
+    next_id: u32,
+    /// The checkpoint that the present descends from.
+    current: u32,
+    /// Commands issued since the current checkpoint.
+    inputs: Vec<(u32, Command)>,
+    /// True while re-simulating, so that replayed commands aren't logged again
+    /// and no new checkpoints are made.
+    replaying: bool,
+    /// The most recently stored snapshot and its decoded bytes, which the next
+    /// snapshot is encoded against. Counted in memory_used.
+    last_snapshot: Option<(u32, Vec<u8>)>,
+    /// Bytes used by snapshots, including the decoded copy of the last one.
+    memory_used: usize,
+    memory_budget: usize,
+}
+
+impl Timeline {
+    /// Start a timeline whose root is the initial state of the level.
+    pub fn new(core_state: &CoreState, memory_budget: usize) -> Timeline {
+        let mut timeline = Timeline {
+            checkpoints: Map::default(),
+            next_id: 1,
+            current: 0,
+            inputs: Vec::new(),
+            replaying: false,
+            last_snapshot: None,
+            memory_used: 0,
+            memory_budget,
+        };
+        timeline.checkpoints.insert(
+            0,
+            Checkpoint {
+                parent: None,
+                depth: 0,
+                wave: core_state.wave_spawner.next_wave_index(),
+                tick: core_state.tick,
+                snapshot: None,
+                inputs: Vec::new(),
+            },
+        );
+        timeline.store(0, serialize(core_state));
+        timeline
+    }
+
+    pub fn is_replaying(&self) -> bool {
+        self.replaying
+    }
+
+    /// The most recently made child of a checkpoint.
+    fn latest_child(&self, id: u32) -> Option<u32> {
+        self.checkpoints
+            .iter()
+            .rev()
+            .find(|(_, checkpoint)| checkpoint.parent == Some(id))
+            .map(|(&id, _)| id)
+    }
+
+    /// Store serialized core state as a checkpoint's snapshot.
+    fn store(&mut self, id: u32, bytes: Vec<u8>) {
+        let base = self.last_snapshot.as_ref().and_then(|(base, base_bytes)| {
+            let chain = self.checkpoints.get(base)?.snapshot.as_ref()?.chain;
+            if chain < MAX_DELTA_CHAIN {
+                Some((*base, chain, base_bytes))
+            } else {
+                None
+            }
+        });
+        let snapshot = match base {
+            Some((base, chain, base_bytes)) => Snapshot {
+                base: Some(base),
+                chain: chain + 1,
+                bytes: encode_delta(base_bytes, &bytes),
+            },
+            None => Snapshot {
+                base: None,
+                chain: 0,
+                bytes: bytes.clone(),
+            },
+        };
+        if let Some(checkpoint) = self.checkpoints.get_mut(&id) {
+            self.memory_used += snapshot.bytes.len();
+            if let Some(old) = checkpoint.snapshot.replace(snapshot) {
+                self.memory_used -= old.bytes.len();
+            }
+            self.set_last_snapshot(Some((id, bytes)));
+        }
+    }
+
+    /// Replace the decoded copy of the most recent snapshot, which counts
+    /// towards the memory budget like the snapshots do.
+    fn set_last_snapshot(&mut self, last_snapshot: Option<(u32, Vec<u8>)>) {
+        if let Some((_, bytes)) = &last_snapshot {
+            self.memory_used += bytes.len();
+        }
+        if let Some((_, old)) = std::mem::replace(&mut self.last_snapshot, last_snapshot) {
+            self.memory_used -= old.len();
+        }
+    }
+
+    /// Decode a checkpoint's snapshot into serialized core state.
+    fn decode(&self, id: u32) -> Option<Vec<u8>> {
+        match &self.last_snapshot {
+            Some((last, bytes)) if *last == id => return Some(bytes.clone()),
+            _ => {}
+        }
+        let snapshot = self.checkpoints.get(&id)?.snapshot.as_ref()?;
+        match snapshot.base {
+            Some(base) => apply_delta(&self.decode(base)?, &snapshot.bytes),
+            None => Some(snapshot.bytes.clone()),
+        }
+    }
+}
+
+impl World {
+    /// Record a command so that it can be replayed later.
+    pub fn log_command(&mut self, command: Command) {
+        // Any command might change how long the build queue takes
+        self.render_state.build_etas = None;
+        if !self.timeline.replaying {
+            self.timeline.inputs.push((self.core_state.tick, command));
+        }
+    }
+
+    fn apply_command(&mut self, command: Command) {
+        match command {
+            Command::BuildTower {
+                row,
+                col,
+                tower_index,
+            } => self.queue_build_tower(row, col, tower_index),
+            Command::CancelConstruction { row, col } => self.cancel_construction(row, col),
+            Command::SendNextWave => self.send_next_wave(),
+            Command::QueueUpgrade {
+                row,
+                col,
+                upgrade_index,
+            } => self.queue_upgrade(row, col, upgrade_index),
+            Command::SetFactoryMode { row, col, mode } => self.set_factory_mode(row, col, mode),
+            Command::MoveBuildOrderToFront { index } => self.move_build_order_to_front(index),
+            Command::MoveBuildOrderToBack { index } => self.move_build_order_to_back(index),
+            Command::SwapBuildOrders { a, b } => self.swap_build_orders(a, b),
+            Command::ToggleBuildOrderPaused { index } => self.toggle_build_order_paused(index),
+        }
+    }
+
+    /// Make a checkpoint of the present as a child of the current checkpoint.
+    pub fn save(&mut self) {
+        if self.timeline.replaying {
+            return;
+        }
+        let timeline = &mut self.timeline;
+        let tick = self.core_state.tick;
+        let current = match timeline.checkpoints.get(&timeline.current) {
+            Some(current) => current,
+            None => return,
+        };
+        if current.tick == tick && timeline.inputs.is_empty() {
+            // The present is the current checkpoint
+            return;
+        }
+
+        // Reuse an identical branch if we have been here before
+        let existing = timeline.checkpoints.iter().find(|(_, checkpoint)| {
+            checkpoint.parent == Some(timeline.current)
+                && checkpoint.tick == tick
+                && checkpoint.inputs == timeline.inputs
+        });
+        if let Some((&id, _)) = existing {
+            timeline.current = id;
+            timeline.inputs.clear();
+            return;
+        }
+
+        let id = timeline.next_id;
+        timeline.next_id += 1;
+        let depth = current.depth + 1;
+        timeline.checkpoints.insert(
+            id,
+            Checkpoint {
+                parent: Some(timeline.current),
+                depth,
+                wave: self.core_state.wave_spawner.next_wave_index(),
+                tick,
+                snapshot: None,
+                inputs: std::mem::take(&mut timeline.inputs),
+            },
+        );
+        timeline.current = id;
+        if depth % KEYFRAME_INTERVAL == 0 {
+            timeline.store(id, serialize(&self.core_state));
+            self.enforce_memory_budget();
+        }
+    }
+
+    /// Jump to a checkpoint, keeping the present as a checkpoint that can be
+    /// returned to.
+    fn jump_to(&mut self, id: u32) {
+        self.save();
+        if !self.timeline.checkpoints.contains_key(&id) {
+            // Evicted while saving the present
+            return;
+        }
+        if !self.load_checkpoint(id) {
+            return;
+        }
+        self.timeline.current = id;
+        self.timeline.inputs.clear();
+        // Avoid weird time travel & underflow with visuals
+        self.render_state = Default::default();
+    }
+
+    /// Set the core state to the state at a checkpoint, re-simulating from the
+    /// nearest stored ancestor if needed. Returns false, leaving the core state
+    /// alone, if the checkpoint can't be rebuilt.
+    fn load_checkpoint(&mut self, id: u32) -> bool {
+        let mut path = Vec::new();
+        let mut id = id;
+        let state = loop {
+            let checkpoint = match self.timeline.checkpoints.get(&id) {
+                Some(checkpoint) => checkpoint,
+                None => return false,
+            };
+            match (&checkpoint.snapshot, checkpoint.parent) {
+                (Some(_), _) => {
+                    match self
+                        .timeline
+                        .decode(id)
+                        .and_then(|bytes| deserialize(&bytes))
+                    {
+                        Some(state) => break state,
+                        None => return false,
+                    }
+                }
+                (None, Some(parent)) => {
+                    path.push(id);
+                    id = parent;
+                }
+                // Checkpoints without parents always have snapshots
+                (None, None) => return false,
+            }
+        };
+        self.core_state = state;
+
+        // Commands can have side effects like unpausing
+        let run_state = self.run_state;
+        // Re-simulating mustn't show up on screen. It might not even be the
+        // branch that is being played.
+        let render_state = std::mem::take(&mut self.render_state);
+        self.timeline.replaying = true;
+        for id in path.into_iter().rev() {
+            if let Some(checkpoint) = self.timeline.checkpoints.get(&id) {
+                let inputs = checkpoint.inputs.clone();
+                let tick = checkpoint.tick;
+                self.replay(inputs, tick);
+            }
+        }
+        self.timeline.replaying = false;
+        self.render_state = render_state;
+        self.run_state = run_state;
+        true
+    }
+
+    /// Step the simulation up to the given tick, applying commands on the
+    /// ticks they were issued.
+    fn replay(&mut self, inputs: Vec<(u32, Command)>, until: u32) {
+        let mut inputs = inputs.into_iter().peekable();
+        loop {
+            while let Some((_, command)) = inputs.next_if(|(tick, _)| *tick <= self.core_state.tick)
+            {
+                self.apply_command(command);
+            }
+            if self.core_state.tick >= until {
+                break;
+            }
+            self.step();
+        }
+    }
+
+    /// Forget the oldest checkpoints until snapshots fit in the memory budget.
+    /// The current checkpoint is never forgotten.
+    fn enforce_memory_budget(&mut self) {
+        while self.timeline.memory_used > self.timeline.memory_budget {
+            let current = self.timeline.current;
+            match self.timeline.checkpoints.keys().find(|&&id| id != current) {
+                Some(&oldest) => self.evict(oldest),
+                None => break,
+            }
+        }
+    }
+
+    /// Forget a checkpoint without a snapshot, along with the descendants that
+    /// are rebuilt from it. Used when it can't be rebuilt anymore.
+    fn forget(&mut self, id: u32) {
+        let children: Vec<u32> = self
+            .timeline
+            .checkpoints
+            .iter()
+            .filter(|(_, checkpoint)| checkpoint.parent == Some(id))
+            .map(|(&child, _)| child)
+            .collect();
+        for child in children {
+            match self.timeline.checkpoints.get_mut(&child) {
+                Some(checkpoint) if checkpoint.snapshot.is_some() => {
+                    checkpoint.parent = None;
+                    checkpoint.inputs.clear();
+                }
+                _ => self.forget(child),
+            }
+        }
+        self.timeline.checkpoints.shift_remove(&id);
+    }
+
+    fn evict(&mut self, id: u32) {
+        // Children that are rebuilt from this checkpoint need snapshots of
+        // their own, since they are about to lose their parent
+        let orphans: Vec<u32> = self
+            .timeline
+            .checkpoints
+            .iter()
+            .filter(|(_, checkpoint)| {
+                checkpoint.parent == Some(id) && checkpoint.snapshot.is_none()
+            })
+            .map(|(&child, _)| child)
+            .collect();
+        if !orphans.is_empty() {
+            let present = self.core_state.clone();
+            for child in orphans {
+                if self.load_checkpoint(child) {
+                    let bytes = serialize(&self.core_state);
+                    self.timeline.store(child, bytes);
+                } else {
+                    self.forget(child);
+                }
+            }
+            self.core_state = present;
+        }
+
+        // Rebase snapshots that are deltas against this checkpoint
+        let dependents: Vec<u32> = self
+            .timeline
+            .checkpoints
+            .iter()
+            .filter(|(_, checkpoint)| {
+                checkpoint
+                    .snapshot
+                    .as_ref()
+                    .map(|snapshot| snapshot.base == Some(id))
+                    .unwrap_or(false)
+            })
+            .map(|(&dependent, _)| dependent)
+            .collect();
+        for dependent in dependents {
+            if let Some(bytes) = self.timeline.decode(dependent) {
+                let memory_used = &mut self.timeline.memory_used;
+                if let Some(checkpoint) = self.timeline.checkpoints.get_mut(&dependent) {
+                    *memory_used += bytes.len();
+                    let old = checkpoint.snapshot.replace(Snapshot {
+                        base: None,
+                        chain: 0,
+                        bytes,
+                    });
+                    if let Some(old) = old {
+                        *memory_used -= old.bytes.len();
+                    }
+                }
+            }
+        }
+
+        for checkpoint in self.timeline.checkpoints.values_mut() {
+            if checkpoint.parent == Some(id) {
+                checkpoint.parent = None;
+                checkpoint.inputs.clear();
+            }
+        }
+        if let Some(checkpoint) = self.timeline.checkpoints.shift_remove(&id) {
+            if let Some(snapshot) = checkpoint.snapshot {
+                self.timeline.memory_used -= snapshot.bytes.len();
+            }
+        }
+        if let Some((last, _)) = self.timeline.last_snapshot {
+            if last == id {
+                self.timeline.set_last_snapshot(None);
+            }
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl World {
+    /// Go back to the current checkpoint, or the one before it if the current
+    /// checkpoint was only a moment ago.
+    pub fn restore(&mut self) {
+        let timeline = &self.timeline;
+        let target = match timeline.checkpoints.get(&timeline.current) {
+            Some(current)
+                if self.core_state.tick.saturating_sub(current.tick) <= RESTORE_GRACE_TICKS =>
+            {
+                current.parent.unwrap_or(timeline.current)
+            }
+            _ => timeline.current,
+        };
+        self.jump_to(target);
+    }
+
+    /// Go forward to the most recent checkpoint made after the current one.
+    pub fn redo(&mut self) {
+        if let Some(target) = self.timeline.latest_child(self.timeline.current) {
+            self.jump_to(target);
+        }
+    }
+
+    pub fn restore_checkpoint(&mut self, id: u32) {
+        if self.timeline.checkpoints.contains_key(&id) {
+            self.jump_to(id);
+        }
+    }
+
+    /// Ids of every checkpoint, from oldest to newest.
+    pub fn query_checkpoints(&self) -> Vec<u32> {
+        self.timeline.checkpoints.keys().copied().collect()
+    }
+
+    pub fn current_checkpoint(&self) -> u32 {
+        self.timeline.current
+    }
+
+    /// Id of the checkpoint's parent, or -1 if it has none.
+    pub fn query_checkpoint_parent(&self, id: u32) -> i32 {
+        self.timeline
+            .checkpoints
+            .get(&id)
+            .and_then(|checkpoint| checkpoint.parent)
+            .map(|parent| parent as i32)
+            .unwrap_or(-1)
+    }
+
+    pub fn query_checkpoint_wave(&self, id: u32) -> usize {
+        self.timeline
+            .checkpoints
+            .get(&id)
+            .map(|checkpoint| checkpoint.wave)
+            .unwrap_or(0)
+    }
+
+    pub fn query_checkpoint_tick(&self, id: u32) -> u32 {
+        self.timeline
+            .checkpoints
+            .get(&id)
+            .map(|checkpoint| checkpoint.tick)
+            .unwrap_or(0)
+    }
+
+    /// Bytes used to store snapshots.
+    pub fn query_snapshot_memory(&self) -> usize {
+        self.timeline.memory_used
+    }
+}
@@ -6,17 +6,55 @@ use wasm_bindgen::prelude::*;
 use crate::{
     build::{BuildOrder, BuildType},
     config::Config,
-    map::{Constants, Tile, TRUE_MAP_WIDTH},
+    map::{Constants, Terrain, Tile, TRUE_MAP_WIDTH},
     world::{Map, World},
 };
 
+impl World {
+    /// Whether the tile's terrain lets a tower of this type be built on it,
+    /// regardless of any tower already there.
+    pub fn terrain_allows_tower(&self, row: usize, col: usize, tower_index: usize) -> bool {
+        let index = (row + 2) * TRUE_MAP_WIDTH + col + 2;
+        if self.level_state.map.get(index) != Some(&Tile::Empty) {
+            return false;
+        }
+        match self.level_state.terrain.get(index) {
+            Some(Terrain::Rock) => false,
+            Some(Terrain::Water) => self.config.get_common(tower_index).builds_on_water,
+            _ => true,
+        }
+    }
+
+    /// The terrain under a tile, in visible tile coordinates.
+    pub fn terrain_at(&self, row: usize, col: usize) -> Terrain {
+        self.level_state
+            .terrain
+            .get((row + 2) * TRUE_MAP_WIDTH + col + 2)
+            .copied()
+            .unwrap_or(Terrain::Plain)
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Tower {
     pub row: usize,
     pub col: usize,
     pub range: f32,
+    /// Base rate of fire plus bonuses from synergies.
+    pub rate_of_fire: f32,
+    /// Multiplier on the hazard emitted by fire towers.
+    pub burning: f32,
     pub type_index: usize,
     pub status: TowerStatus,
+    /// Bit flags of the built upgrades, indexed like the tower type's
+    /// upgrades.
+    pub upgrades: u8,
+}
+
+impl Tower {
+    pub fn has_upgrade(&self, upgrade_flag: u8) -> bool {
+        self.upgrades & upgrade_flag != 0
+    }
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Eq, Clone)]
@@ -49,8 +87,11 @@ pub fn create_tower(
             row,
             col,
             range: base_tower.base_range * f32::TILE_SIZE,
+            rate_of_fire: base_tower.base_rate_of_fire,
+            burning: 1.0,
             type_index,
             status: TowerStatus::Queued,
+            upgrades: 0,
         },
     );
     towers_by_pos.insert((row, col), entity);
@@ -150,15 +191,8 @@ impl World {
             .to_owned()
     }
 
-    pub fn query_can_build_tower(&self, row: usize, col: usize) -> bool {
+    pub fn query_can_build_tower(&self, row: usize, col: usize, tower_index: usize) -> bool {
         let has_tower = self.core_state.towers_by_pos.contains_key(&(row, col));
-        let true_row = row + 2;
-        let true_col = col + 2;
-        let empty_terrain = self
-            .level_state
-            .map
-            .get(true_row * TRUE_MAP_WIDTH + true_col)
-            == Some(&Tile::Empty);
-        !has_tower && empty_terrain
+        !has_tower && self.terrain_allows_tower(row, col, tower_index)
     }
 }
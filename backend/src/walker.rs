@@ -1,23 +1,44 @@
 use serde::{Deserialize, Serialize};
 
 use crate::{
+    config::Config,
     distance::fast_distance,
+    events::{Event, EventType},
     graphics::{SpriteData, SpriteType},
-    map::{true_row_col, Constants, Tile, TRUE_MAP_WIDTH},
+    map::{
+        distances::Distance, true_row_col, true_tile_center, Constants, Terrain, Tile,
+        TRUE_MAP_WIDTH,
+    },
     mob::Mob,
+    waves::spawn_enemy,
     world::{Map, World},
 };
 
 pub const STANDARD_ENEMY_RADIUS: f32 = 0.3 * f32::TILE_SIZE;
 
+/// Distance along the path between walkers spawned around another walker.
+const SPAWN_SPACING: f32 = 0.4 * f32::TILE_SIZE;
+
 /// A walker is an entity that travels along the map's path.
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Walker {
     pub speed: f32,
+    /// Index into the config's enemy types.
+    pub enemy_type: usize,
+    /// Index of the wave the walker belongs to. Walkers spawned by other
+    /// walkers belong to the same wave.
+    pub wave: usize,
 }
 
 impl Walker {
-    pub fn dump(&self, id: &u32, data: &mut SpriteData, mobs: &Map<u32, Mob>, frame_fudge: f32) {
+    pub fn dump(
+        &self,
+        id: &u32,
+        data: &mut SpriteData,
+        mobs: &Map<u32, Mob>,
+        config: &Config,
+        frame_fudge: f32,
+    ) {
         if let Some(mob) = mobs.get(id) {
             data.push(
                 SpriteType::Walker as u8,
@@ -25,7 +46,7 @@ impl Walker {
                 mob.y + frame_fudge * (mob.y - mob.old_y),
                 0.0,
                 1.0,
-                0x777777,
+                config.get_enemy(self.enemy_type).color,
             );
         }
     }
@@ -33,11 +54,29 @@ impl Walker {
 
 impl World {
     pub fn walk(&mut self) {
-        for (entity, walker) in &mut self.core_state.walkers {
+        let slowdowns: Vec<f32> = self
+            .core_state
+            .walkers
+            .iter()
+            .map(|(entity, walker)| match self.core_state.mobs.get(entity) {
+                Some(mob) => self.hazard_slowdown(walker.enemy_type, mob.x, mob.y),
+                None => 1.0,
+            })
+            .collect();
+        for ((entity, walker), slowdown) in self.core_state.walkers.iter_mut().zip(slowdowns) {
             if let Some(mob) = self.core_state.mobs.get_mut(entity) {
                 let (true_row, true_col) = true_row_col(mob.x, mob.y);
 
-                let mut speed = walker.speed;
+                let mut speed = walker.speed * slowdown;
+
+                // Walk slower through mud
+                let terrain = self
+                    .level_state
+                    .terrain
+                    .get(true_row * TRUE_MAP_WIDTH + true_col);
+                if terrain == Some(&Terrain::Mud) {
+                    speed *= self.config.terrain.mud_speed;
+                }
 
                 // Walk slower if under the effects of an external impulse
                 if let Some(impulse) = self.core_state.impulses.get(entity) {
@@ -56,6 +95,111 @@ impl World {
             }
         }
     }
+
+    /// If a dying walker's enemy type splits, spawn its children. Children are
+    /// spread out along the path around the parent so that they keep the
+    /// parent's progress toward the exit.
+    pub fn split_walker(&mut self, entity: u32) {
+        let walker = self.core_state.walkers.get(&entity);
+        let mob = self.core_state.mobs.get(&entity);
+        let (enemy_type, wave, x, y) = match (walker, mob) {
+            (Some(walker), Some(mob)) => (walker.enemy_type, walker.wave, mob.x, mob.y),
+            _ => return,
+        };
+
+        let enemy = self.config.get_enemy(enemy_type);
+        if let Some(name) = &enemy.splits_into {
+            let child_type = self.config.enemy_index(name);
+            self.spawn_along_path(child_type, enemy.split_count, wave, x, y);
+        }
+    }
+
+    /// Spawn a number of walkers spread out along the path, centered on (x, y).
+    /// Entity ids are allocated in order along the path, so this is
+    /// deterministic.
+    pub fn spawn_along_path(&mut self, enemy_type: usize, count: u32, wave: usize, x: f32, y: f32) {
+        for i in 0..count {
+            let offset = (i as f32 - (count - 1) as f32 / 2.0) * SPAWN_SPACING;
+            let mut spawn_x = x;
+            let mut spawn_y = y;
+            let (true_row, true_col) = true_row_col(x, y);
+            walk_tile(
+                &self.level_state.map,
+                true_row,
+                true_col,
+                &mut spawn_x,
+                &mut spawn_y,
+                offset,
+            );
+            let entity = spawn_enemy(
+                &mut self.core_state,
+                &self.config,
+                enemy_type,
+                wave,
+                spawn_x,
+                spawn_y,
+            );
+            self.emit(Event {
+                event_type: EventType::EnemySpawned,
+                entity,
+                x: spawn_x,
+                y: spawn_y,
+                value: enemy_type as f32,
+            });
+        }
+    }
+
+    /// Send walkers that have reached an exit back to the closest entrance,
+    /// and take a life for each.
+    pub fn handle_leaks(&mut self) {
+        let dist_from_exit = &self.level_state.dist_from_exit;
+        let entrances = &self.core_state.wave_spawner.entrances;
+        let mut leaked = Vec::new();
+        for (entity, mob) in &mut self.core_state.mobs {
+            if !self.core_state.walkers.contains_key(entity) {
+                continue;
+            }
+            let (true_row, true_col) = true_row_col(mob.x, mob.y);
+            if !matches!(
+                dist_from_exit.get(true_row * TRUE_MAP_WIDTH + true_col),
+                Some(Some(Distance { tiles: 0, .. }))
+            ) {
+                continue;
+            }
+            let closest = entrances.iter().min_by_key(|&&(row, col)| {
+                let d_row = row as isize - true_row as isize;
+                let d_col = col as isize - true_col as isize;
+                d_row * d_row + d_col * d_col
+            });
+            if let Some(&(row, col)) = closest {
+                leaked.push((*entity, mob.x, mob.y));
+                let (x, y) = true_tile_center(row, col);
+                mob.x = x;
+                mob.y = y;
+            }
+        }
+
+        for (entity, x, y) in leaked {
+            let lives_before = self.core_state.lives;
+            self.core_state.lives = lives_before.saturating_sub(1);
+            self.emit(Event {
+                event_type: EventType::EnemyLeaked,
+                entity,
+                x,
+                y,
+                value: self.core_state.lives as f32,
+            });
+            if lives_before == 1 {
+                self.emit(Event {
+                    event_type: EventType::GameOver,
+                    entity: 0,
+                    x,
+                    y,
+                    value: self.core_state.wave_spawner.current_wave() as f32,
+                });
+            }
+        }
+    }
 }
 
 /// Unit vectors pointing in cardinal directions. A representation of direction
@@ -7,19 +7,20 @@ use serde::{Deserialize, Serialize};
 use wasm_bindgen::prelude::*;
 
 use crate::{
-    explosion::Impulse,
+    aura::{Healer, ShieldBearer},
+    boss::Boss,
+    config::Config,
+    endless::generate_wave,
+    events::{Event, EventType},
     health::Health,
     map::true_tile_center,
     mob::Mob,
+    timeline::Command,
     walker::Walker,
-    world::{CoreState, Map, World},
+    world::{CoreState, World},
 };
 
-const TICKS_PER_SECOND: u32 = 60;
-const SECONDS_PER_WAVE: u32 = 20;
-const TICKS_PER_WAVE: u32 = TICKS_PER_SECOND * SECONDS_PER_WAVE;
-const TICKS_BETWEEN_SPAWNS: u32 = 21;
-const TICKS_TILL_FIRST_WAVE: u32 = 180;
+pub const TICKS_PER_SECOND: u32 = 60;
 
 #[derive(Serialize, Deserialize, Default, Clone)]
 pub struct WaveSpawner {
@@ -27,27 +28,51 @@ pub struct WaveSpawner {
     ticks_till_next_wave: u32,
     next_wave_index: usize,
     queued_enemies: BinaryHeap<Reverse<QueuedEnemy>>,
+    /// Waves that have been sent and still have enemies left to spawn or
+    /// walk, oldest first.
+    uncleared_waves: Vec<usize>,
 }
 
 impl WaveSpawner {
-    pub fn new(entrances: Vec<(usize, usize)>) -> WaveSpawner {
+    pub fn new(entrances: Vec<(usize, usize)>, config: &Config) -> WaveSpawner {
         WaveSpawner {
             entrances,
-            ticks_till_next_wave: TICKS_TILL_FIRST_WAVE,
+            ticks_till_next_wave: seconds_to_ticks(config.wave_interval(0)),
             next_wave_index: 0,
             queued_enemies: BinaryHeap::new(),
+            uncleared_waves: Vec::new(),
         }
     }
+
+    pub fn next_wave_index(&self) -> usize {
+        self.next_wave_index
+    }
+
+    /// Index of the most recently sent wave, or 0 before the first wave.
+    pub fn current_wave(&self) -> usize {
+        self.next_wave_index.saturating_sub(1)
+    }
 }
 
-#[derive(Serialize, Deserialize, Default, PartialEq, Eq, Clone)]
+#[derive(Serialize, Deserialize, Default, Clone)]
 struct QueuedEnemy {
     true_row: usize,
     true_col: usize,
     spawn_tick: u32,
-    enemy_type: Enemy,
+    enemy_type: usize,
+    wave: usize,
+    health_multiplier: f32,
+    speed_multiplier: f32,
+}
+
+impl PartialEq for QueuedEnemy {
+    fn eq(&self, other: &Self) -> bool {
+        self.spawn_tick == other.spawn_tick
+    }
 }
 
+impl Eq for QueuedEnemy {}
+
 impl Ord for QueuedEnemy {
     fn cmp(&self, other: &Self) -> Ordering {
         self.spawn_tick.cmp(&other.spawn_tick)
@@ -60,77 +85,185 @@ impl PartialOrd for QueuedEnemy {
     }
 }
 
-#[derive(Serialize, Deserialize, Default)]
+#[derive(Serialize, Deserialize, Default, Clone)]
 pub struct Wave {
-    group: Vec<Group>,
+    pub group: Vec<Group>,
+    /// Overrides the level's wave interval for the time before this wave.
+    pub interval: Option<f32>,
+    /// Overrides the level's wait_for_clear for this wave.
+    pub wait_for_clear: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct Group {
+    pub size: u32,
+    /// Name of an enemy type from the config.
+    pub r#type: String,
+    /// Seconds after the start of the wave before the group starts spawning.
+    /// Without a delay, the group starts once the previous group is done.
+    pub delay: Option<f32>,
+    /// Seconds between spawns from the same entrance.
+    pub spacing: Option<f32>,
+    /// Entrance or entrances to spawn from, as indices into the map's
+    /// entrances. Enemies take turns between the entrances. Defaults to all
+    /// entrances.
+    pub entrance: Option<Entrance>,
+    #[serde(default = "one")]
+    pub health_multiplier: f32,
+    #[serde(default = "one")]
+    pub speed_multiplier: f32,
+}
+
+impl Group {
+    pub fn new(size: u32, r#type: String) -> Group {
+        Group {
+            size,
+            r#type,
+            delay: None,
+            spacing: None,
+            entrance: None,
+            health_multiplier: 1.0,
+            speed_multiplier: 1.0,
+        }
+    }
 }
 
-#[derive(Serialize, Deserialize, Default)]
-struct Group {
-    size: u32,
-    r#type: Enemy,
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum Entrance {
+    One(usize),
+    Many(Vec<usize>),
 }
 
-#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
-pub enum Enemy {
-    Circle,
-    Triangle,
-    Square,
+fn one() -> f32 {
+    1.0
 }
 
-impl Default for Enemy {
-    fn default() -> Self {
-        Enemy::Circle
-    }
+fn seconds_to_ticks(seconds: f32) -> u32 {
+    (seconds * TICKS_PER_SECOND as f32).round() as u32
 }
 
 impl World {
-    pub fn save_if_wave_start(&mut self) {
-        if self.core_state.wave_spawner.ticks_till_next_wave == 0 {
-            if self
+    /// Whether there is a wave with the given index, authored or generated.
+    fn has_wave(&self, index: usize) -> bool {
+        index < self.config.waves.len() || self.config.endless.is_some()
+    }
+
+    /// Get an authored wave, or generate one if the authored waves have run out
+    /// and endless mode is on.
+    fn get_wave(&self, index: usize) -> Option<Wave> {
+        match (self.config.waves.get(index), &self.config.endless) {
+            (Some(wave), _) => Some(wave.clone()),
+            (None, Some(endless)) => Some(generate_wave(
+                &self.config,
+                endless,
+                self.core_state.seed,
+                index - self.config.waves.len(),
+            )),
+            (None, None) => None,
+        }
+    }
+
+    /// Whether the countdown to the next wave is on hold until the enemies on
+    /// the field are gone.
+    fn is_waiting_for_clear(&self) -> bool {
+        let wave_spawner = &self.core_state.wave_spawner;
+        self.has_wave(wave_spawner.next_wave_index)
+            && self
                 .config
-                .waves
-                .get(self.core_state.wave_spawner.next_wave_index)
-                .is_some()
-            {
-                self.save();
-            }
+                .wave_waits_for_clear(wave_spawner.next_wave_index)
+            && (!wave_spawner.queued_enemies.is_empty() || !self.core_state.walkers.is_empty())
+    }
+
+    pub fn save_if_wave_start(&mut self) {
+        if self.core_state.wave_spawner.ticks_till_next_wave == 0
+            && self.has_wave(self.core_state.wave_spawner.next_wave_index)
+        {
+            self.save();
         }
     }
 
     pub fn spawn_mobs(&mut self) {
         // Queue waves
         if self.core_state.wave_spawner.ticks_till_next_wave == 0 {
-            if let Some(wave) = self
-                .config
-                .waves
-                .get(self.core_state.wave_spawner.next_wave_index)
-            {
-                self.core_state.wave_spawner.ticks_till_next_wave = TICKS_PER_WAVE - 1;
+            let index = self.core_state.wave_spawner.next_wave_index;
+            if let Some(wave) = self.get_wave(index) {
                 self.core_state.wave_spawner.next_wave_index += 1;
-                queue_wave(&mut self.core_state, wave);
+                // This tick counts towards the interval
+                let interval = self
+                    .config
+                    .wave_interval(self.core_state.wave_spawner.next_wave_index);
+                self.core_state.wave_spawner.ticks_till_next_wave =
+                    seconds_to_ticks(interval).saturating_sub(1);
+                queue_wave(&mut self.core_state, &self.config, &wave, index);
+                self.core_state.wave_spawner.uncleared_waves.push(index);
+                self.collect_factory_income();
+                self.emit(Event {
+                    event_type: EventType::WaveStarted,
+                    entity: 0,
+                    x: 0.0,
+                    y: 0.0,
+                    value: index as f32,
+                });
             }
-        } else {
+        } else if !self.is_waiting_for_clear() {
             self.core_state.wave_spawner.ticks_till_next_wave -= 1;
         }
 
         // Spawn queued mobs
         while let Some(Reverse(queued_enemy)) = self.core_state.wave_spawner.queued_enemies.peek() {
             if queued_enemy.spawn_tick == self.core_state.tick {
-                spawn_enemy(
-                    self.core_state.entity_ids.next(),
-                    queued_enemy.true_row,
-                    queued_enemy.true_col,
-                    &mut self.core_state.mobs,
-                    &mut self.core_state.walkers,
-                    &mut self.core_state.impulses,
-                    &mut self.core_state.health,
-                );
+                let (x, y) = true_tile_center(queued_enemy.true_row, queued_enemy.true_col);
+                let QueuedEnemy {
+                    enemy_type,
+                    wave,
+                    health_multiplier,
+                    speed_multiplier,
+                    ..
+                } = *queued_enemy;
                 self.core_state.wave_spawner.queued_enemies.pop();
+                let entity =
+                    spawn_enemy(&mut self.core_state, &self.config, enemy_type, wave, x, y);
+                if let Some(walker) = self.core_state.walkers.get_mut(&entity) {
+                    walker.speed *= speed_multiplier;
+                }
+                if let Some(health) = self.core_state.health.get_mut(&entity) {
+                    health.max_health *= health_multiplier;
+                    health.curr_health *= health_multiplier;
+                }
+                self.emit(Event {
+                    event_type: EventType::EnemySpawned,
+                    entity,
+                    x,
+                    y,
+                    value: enemy_type as f32,
+                });
             } else {
                 break;
             }
         }
+
+        // A wave is cleared once none of its enemies are left to spawn or walk
+        let wave_spawner = &self.core_state.wave_spawner;
+        let walkers = &self.core_state.walkers;
+        let (cleared, uncleared): (Vec<usize>, Vec<usize>) =
+            wave_spawner.uncleared_waves.iter().partition(|&&index| {
+                !wave_spawner
+                    .queued_enemies
+                    .iter()
+                    .any(|Reverse(queued_enemy)| queued_enemy.wave == index)
+                    && !walkers.values().any(|walker| walker.wave == index)
+            });
+        self.core_state.wave_spawner.uncleared_waves = uncleared;
+        for index in cleared {
+            self.emit(Event {
+                event_type: EventType::WaveCleared,
+                entity: 0,
+                x: 0.0,
+                y: 0.0,
+                value: index as f32,
+            });
+        }
     }
 }
 
@@ -141,12 +274,13 @@ impl World {
     /// still-spawning wave.
     /// If no enemies are spawning, this index will be the index of the next
     /// wave to spawn.
-    /// If the last wave has finished spawning, return -1.
+    /// If the last wave has finished spawning, return -1. In endless mode there
+    /// is no last wave.
     pub fn next_wave_index(&self) -> i32 {
         let wave_spawner = &self.core_state.wave_spawner;
         if !wave_spawner.queued_enemies.is_empty() {
             wave_spawner.next_wave_index.saturating_sub(1) as i32
-        } else if wave_spawner.next_wave_index < self.config.waves.len() {
+        } else if self.has_wave(wave_spawner.next_wave_index) {
             wave_spawner.next_wave_index as i32
         } else {
             -1
@@ -155,33 +289,67 @@ impl World {
 
     /// Number of ticks until the next wave spawns, or 0 if a wave is currently
     /// spawning or no more waves remain.
+    /// While waiting for the field to clear, this is the number of ticks that
+    /// will remain once the field is clear.
     pub fn ticks_till_next_wave(&self) -> u32 {
         let wave_spawner = &self.core_state.wave_spawner;
         if !wave_spawner.queued_enemies.is_empty() {
             0
-        } else if wave_spawner.next_wave_index < self.config.waves.len() {
+        } else if self.has_wave(wave_spawner.next_wave_index) {
             wave_spawner.ticks_till_next_wave
         } else {
             0
         }
     }
 
+    /// Whether the next wave is waiting for the field to clear before its
+    /// countdown continues.
+    pub fn waiting_for_clear(&self) -> bool {
+        self.core_state.wave_spawner.queued_enemies.is_empty() && self.is_waiting_for_clear()
+    }
+
+    /// Send the next wave now. Skipping the wait earns bonus build time.
     pub fn send_next_wave(&mut self) {
+        self.log_command(Command::SendNextWave);
+
+        let wave_spawner = &self.core_state.wave_spawner;
+        if self.has_wave(wave_spawner.next_wave_index) {
+            let skipped = wave_spawner.ticks_till_next_wave as f32;
+            self.core_state.stockpile += skipped * self.config.timing.early_call_bonus;
+        }
         self.core_state.wave_spawner.ticks_till_next_wave = 0;
     }
 }
 
-fn queue_wave(core_state: &mut CoreState, wave: &Wave) {
-    let mut i = 0;
+fn queue_wave(core_state: &mut CoreState, config: &Config, wave: &Wave, wave_index: usize) {
+    let all_entrances = core_state.wave_spawner.entrances.clone();
+    if all_entrances.is_empty() {
+        return;
+    }
+    // Tick (relative to the start of the wave) when the previous group ended
+    let mut previous_end = 0;
 
     for group in &wave.group {
-        for _ in 0..group.size {
-            let entrance_i = i % core_state.wave_spawner.entrances.len();
-            let tick_delay =
-                (i / core_state.wave_spawner.entrances.len()) as u32 * TICKS_BETWEEN_SPAWNS;
-            i += 1;
+        let enemy_type = config.enemy_index(&group.r#type);
+        let start = group.delay.map(seconds_to_ticks).unwrap_or(previous_end);
+        let spacing = group.spacing.unwrap_or(config.timing.spawn_spacing);
+        let spacing = seconds_to_ticks(spacing);
+        let mut entrances: Vec<(usize, usize)> = match &group.entrance {
+            Some(Entrance::One(i)) => all_entrances.get(*i).copied().into_iter().collect(),
+            Some(Entrance::Many(indices)) => indices
+                .iter()
+                .filter_map(|&i| all_entrances.get(i).copied())
+                .collect(),
+            None => Vec::new(),
+        };
+        // Fall back to every entrance if none of the given ones exist
+        if entrances.is_empty() {
+            entrances = all_entrances.clone();
+        }
 
-            let (true_row, true_col) = core_state.wave_spawner.entrances[entrance_i];
+        for i in 0..group.size as usize {
+            let (true_row, true_col) = entrances[i % entrances.len()];
+            let tick_delay = start + (i / entrances.len()) as u32 * spacing;
             core_state
                 .wave_spawner
                 .queued_enemies
@@ -189,25 +357,60 @@ fn queue_wave(core_state: &mut CoreState, wave: &Wave) {
                     true_row,
                     true_col,
                     spawn_tick: core_state.tick + tick_delay,
-                    enemy_type: group.r#type,
+                    enemy_type,
+                    wave: wave_index,
+                    health_multiplier: group.health_multiplier,
+                    speed_multiplier: group.speed_multiplier,
                 }));
         }
+
+        let rows = (group.size as usize + entrances.len() - 1) / entrances.len();
+        previous_end = start + rows as u32 * spacing;
     }
 }
 
+/// Spawn an enemy of a certain type (an index into the config's enemy types)
+/// and return its entity id.
 pub fn spawn_enemy(
-    entity: u32,
-    true_row: usize,
-    true_col: usize,
-    mobs: &mut Map<u32, Mob>,
-    walkers: &mut Map<u32, Walker>,
-    impulses: &mut Map<u32, Impulse>,
-    health: &mut Map<u32, Health>,
-) {
-    let id = entity;
-    let (x, y) = true_tile_center(true_row, true_col);
-    mobs.insert(id, Mob::new(x, y));
-    walkers.insert(id, Walker { speed: 1.5 });
-    impulses.insert(id, Default::default());
-    health.insert(entity, Health::new(100.0));
+    core_state: &mut CoreState,
+    config: &Config,
+    enemy_type: usize,
+    wave: usize,
+    x: f32,
+    y: f32,
+) -> u32 {
+    let entity = core_state.entity_ids.next();
+    let enemy = config.get_enemy(enemy_type);
+    let difficulty = &core_state.difficulty;
+    let speed = enemy.speed * difficulty.enemy_speed;
+    let health = enemy.health * difficulty.enemy_health;
+    core_state.mobs.insert(entity, Mob::new(x, y));
+    core_state.walkers.insert(
+        entity,
+        Walker {
+            speed,
+            enemy_type,
+            wave,
+        },
+    );
+    core_state.impulses.insert(entity, Default::default());
+    core_state.health.insert(entity, Health::new(health));
+    if enemy.heal.is_some() {
+        core_state.healers.insert(entity, Healer { countdown: 0 });
+    }
+    if enemy.shield.is_some() {
+        core_state
+            .shield_bearers
+            .insert(entity, ShieldBearer { countdown: 0 });
+    }
+    if enemy.boss {
+        core_state.bosses.insert(
+            entity,
+            Boss {
+                next_phase: 0,
+                immune_to: Vec::new(),
+            },
+        );
+    }
+    entity
 }
@@ -6,22 +6,29 @@ use serde::{Deserialize, Serialize};
 use wasm_bindgen::prelude::*;
 
 use crate::{
+    aura::{Healer, Shield, ShieldBearer},
+    boss::Boss,
     build::BuildOrder,
-    config::Config,
+    config::{Config, Difficulty},
+    editor::uncorner,
+    events::Event,
     explosion::{Explosion, Impulse},
     factory::Factory,
     falcon::{Falcon, TargetIndicator},
+    fluid::FluidGrid,
     graphics::RenderState,
-    health::Health,
+    health::{Damage, Health},
     map::{
         distances::{generate_dist_from_entrance, generate_dist_from_exit, Distances},
-        entrances, parse, render_map, Constants, Tile, MAP_0, MAP_WIDTH,
+        entrances, parse, parse_terrain, render_map, Terrain, Tile, MAP_0,
     },
     missile::{Missile, MissileSpawner},
     mob::Mob,
     pusillanimous::Pusillanimous,
+    stats::Stats,
     swallow::{Swallow, SwallowAfterImage, SwallowTargeter},
     targeting::Threat,
+    timeline::Timeline,
     tower::Tower,
     walker::Walker,
     waves::WaveSpawner,
@@ -50,7 +57,10 @@ pub type Map<K, V> = IndexMap<K, V, FnvBuildHasher>;
 #[derive(Serialize, Deserialize, Default, Clone)]
 pub struct CoreState {
     pub tick: u32,
+    #[serde(with = "indexmap::serde_seq")]
+    pub bosses: Map<u32, Boss>,
     pub build_queue: VecDeque<BuildOrder>,
+    pub damage: Vec<Damage>,
     pub entity_ids: EntityIds,
     #[serde(with = "indexmap::serde_seq")]
     pub explosions: Map<u32, Explosion>,
@@ -58,6 +68,9 @@ pub struct CoreState {
     pub factories: Map<u32, Factory>,
     #[serde(with = "indexmap::serde_seq")]
     pub falcons: Map<u32, Falcon>,
+    pub hazard: FluidGrid,
+    #[serde(with = "indexmap::serde_seq")]
+    pub healers: Map<u32, Healer>,
     #[serde(with = "indexmap::serde_seq")]
     pub health: Map<u32, Health>,
     #[serde(with = "indexmap::serde_seq")]
@@ -71,6 +84,11 @@ pub struct CoreState {
     #[serde(with = "indexmap::serde_seq")]
     pub pusillanimous: Map<u32, Pusillanimous>,
     #[serde(with = "indexmap::serde_seq")]
+    pub shield_bearers: Map<u32, ShieldBearer>,
+    #[serde(with = "indexmap::serde_seq")]
+    pub shields: Map<u32, Shield>,
+    pub stats: Stats,
+    #[serde(with = "indexmap::serde_seq")]
     pub swallow_after_images: Map<u32, SwallowAfterImage>,
     #[serde(with = "indexmap::serde_seq")]
     pub swallow_targeters: Map<u32, SwallowTargeter>,
@@ -89,6 +107,14 @@ pub struct CoreState {
     #[serde(with = "indexmap::serde_seq")]
     pub walkers: Map<u32, Walker>,
     pub wave_spawner: WaveSpawner,
+    /// Bonus build time, in ticks, that lets the player build faster until it
+    /// runs out.
+    pub stockpile: f32,
+    /// Seed for anything random, such as endless mode waves.
+    pub seed: u64,
+    /// Each enemy that reaches the exit costs a life.
+    pub lives: u32,
+    pub difficulty: Difficulty,
 }
 
 pub struct LevelState {
@@ -96,6 +122,10 @@ pub struct LevelState {
     pub dist_from_entrance: Distances,
     pub dist_from_exit: Distances,
     pub map: Vec<Tile>,
+    pub terrain: Vec<Terrain>,
+    /// The map in its textual representation, except that corners are stored
+    /// as the direction they turn toward. This is what the map editor edits.
+    pub glyphs: Vec<char>,
 }
 
 #[derive(Clone, Copy)]
@@ -118,13 +148,17 @@ pub struct World {
     #[wasm_bindgen(skip)]
     pub render_state: RenderState,
     #[wasm_bindgen(skip)]
-    pub saved_states: Vec<CoreState>,
+    pub timeline: Timeline,
+    #[wasm_bindgen(skip)]
+    pub events: Vec<Event>,
 }
 
 #[wasm_bindgen]
 impl World {
-    pub fn new(config: &str) -> World {
-        let config = match toml::from_str(config) {
+    /// Start a game on the difficulty with the given name. Unknown names,
+    /// like an empty string, start a game on the default difficulty.
+    pub fn new(config: &str, difficulty: &str) -> World {
+        let config: Config = match toml::from_str(config) {
             Ok(config) => config,
             Err(error) => {
                 crate::log(&format!("{}", error));
@@ -133,50 +167,100 @@ impl World {
         };
 
         let map = parse(&MAP_0);
+        let terrain = parse_terrain(&MAP_0);
 
-        render_map(&map);
-
-        // Avoid entity 0 because the fnv hash doesn't like 0s
-        let entity_ids = EntityIds(1);
-
-        // // any initial towers start off operational
-        // for tower in towers.values_mut() {
-        //     tower.status = TowerStatus::Operational;
-        // }
+        render_map(&map, &terrain);
 
-        let wave_spawner = WaveSpawner::new(entrances(&map));
+        let difficulty = config.difficulty(difficulty);
+        let (core_state, timeline) = start_level(&map, &config, difficulty);
 
         World {
             run_state: RunState::AutoPaused,
             config,
-            core_state: CoreState {
-                tick: 0,
-                entity_ids,
-                wave_spawner,
-                ..Default::default()
-            },
+            core_state,
             level_state: LevelState {
                 level_id: 0,
                 dist_from_entrance: generate_dist_from_entrance(&map),
                 dist_from_exit: generate_dist_from_exit(&map),
                 map,
+                terrain,
+                glyphs: uncorner(&MAP_0),
             },
             render_state: Default::default(),
-            saved_states: Vec::new(),
+            timeline,
+            events: Vec::new(),
         }
     }
 
     pub fn update(&mut self) {
         match self.run_state {
-            RunState::Paused | RunState::AutoPaused => return,
-            RunState::Playing => {}
+            RunState::Paused | RunState::AutoPaused => {}
+            RunState::Playing => self.step(),
         }
+    }
+
+    pub fn run_state(&self) -> u8 {
+        self.run_state as u8
+    }
+
+    pub fn query_lives(&self) -> u32 {
+        self.core_state.lives
+    }
+
+    pub fn query_difficulty(&self) -> String {
+        self.core_state.difficulty.name.clone()
+    }
+
+    pub fn play_pause(&mut self) {
+        self.run_state = match self.run_state {
+            RunState::Paused | RunState::AutoPaused => RunState::Playing,
+            RunState::Playing => RunState::Paused,
+        };
+    }
+}
+
+/// The state at the start of a level on a map, and a timeline rooted there.
+pub fn start_level(map: &[Tile], config: &Config, difficulty: Difficulty) -> (CoreState, Timeline) {
+    // Avoid entity 0 because the fnv hash doesn't like 0s
+    let entity_ids = EntityIds(1);
 
+    // // any initial towers start off operational
+    // for tower in towers.values_mut() {
+    //     tower.status = TowerStatus::Operational;
+    // }
+
+    let wave_spawner = WaveSpawner::new(entrances(map), config);
+    let seed = config
+        .endless
+        .as_ref()
+        .map(|endless| endless.seed)
+        .unwrap_or(0);
+
+    let lives = (config.lives as f32 * difficulty.lives).round().max(1.0) as u32;
+    let core_state = CoreState {
+        tick: 0,
+        entity_ids,
+        wave_spawner,
+        seed,
+        lives,
+        difficulty,
+        ..Default::default()
+    };
+    let memory_budget = (config.timeline.memory_budget * 1_000_000.0) as usize;
+    let timeline = Timeline::new(&core_state, memory_budget);
+    (core_state, timeline)
+}
+
+impl World {
+    /// Advance the simulation by one tick.
+    pub fn step(&mut self) {
         // Never do anything before saving
         self.save_if_wave_start();
 
         self.remember_mob_positions();
         self.update_pusillanimity();
+        self.update_auras();
+        self.update_hazard();
         self.walk();
         self.fly_missiles();
         self.swallow_tower_targeting();
@@ -187,51 +271,15 @@ impl World {
         self.update_explosions();
         self.operate_missile_towers();
         self.update_smoke();
+        self.apply_damage();
+        self.update_boss_phases();
         self.handle_dead();
         self.spawn_mobs();
         self.progress_build();
-        for (entity, mob) in &mut self.core_state.mobs {
-            if self.core_state.walkers.contains_key(entity)
-                && mob.x >= (MAP_WIDTH * usize::TILE_SIZE + usize::TILE_SIZE / 2) as f32
-            {
-                mob.x = -(f32::TILE_SIZE / 2.0);
-            }
-        }
+        self.handle_leaks();
 
         self.core_state.tick += 1;
     }
-
-    pub fn restore(&mut self) {
-        let old_tick = self.core_state.tick;
-        if let Some(saved) = self.saved_states.pop() {
-            self.core_state = saved;
-            // Avoid weird time travel & underflow with visuals
-            self.render_state = Default::default();
-            // Go back one more time if the restore was <= 3s.
-            // If we don't do this, it is nigh impossible to restore more than
-            // one save state back (since restoring immediately saves again)
-            if old_tick - self.core_state.tick <= 3 * 60 {
-                self.restore();
-            }
-        }
-    }
-
-    pub fn run_state(&self) -> u8 {
-        self.run_state as u8
-    }
-
-    pub fn play_pause(&mut self) {
-        self.run_state = match self.run_state {
-            RunState::Paused | RunState::AutoPaused => RunState::Playing,
-            RunState::Playing => RunState::Paused,
-        };
-    }
-}
-
-impl World {
-    pub fn save(&mut self) {
-        self.saved_states.push(self.core_state.clone());
-    }
 }
 
 /// Stores the next available entity id (old ids are not reused)
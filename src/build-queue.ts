@@ -0,0 +1,68 @@
+/* eslint-disable @typescript-eslint/no-unsafe-member-access, @typescript-eslint/no-unsafe-call, @typescript-eslint/no-explicit-any, @typescript-eslint/explicit-module-boundary-types */
+import { bufferInput } from './input';
+
+const list = document.getElementById('build-queue') as HTMLOListElement;
+
+list.addEventListener('click', event => {
+    const button = (event.target as HTMLElement).closest('button');
+    if (!button) {
+        return;
+    }
+    const index = Number(button.dataset.index);
+    switch (button.dataset.action) {
+        case 'front':
+            bufferInput({ type: 'move build order', index, to: 'front' });
+            break;
+        case 'back':
+            bufferInput({ type: 'move build order', index, to: 'back' });
+            break;
+        case 'pause':
+            bufferInput({ type: 'toggle build order paused', index });
+            break;
+    }
+});
+
+// Rebuilding the list every frame would throw away hover states, so we only
+// do it when something visible changed
+let lastRendered = '';
+
+export function renderBuildQueue(world: any, memory: WebAssembly.Memory): void {
+    const count = world.dump_build_queue();
+    const towerTypes = new Uint32Array(memory.buffer, world.build_queue_tower_type(), count);
+    const upgrades = new Uint8Array(memory.buffer, world.build_queue_upgrade(), count);
+    const progress = new Float32Array(memory.buffer, world.build_queue_progress(), count);
+    const costs = new Float32Array(memory.buffer, world.build_queue_cost(), count);
+    const etas = new Float32Array(memory.buffer, world.build_queue_eta(), count);
+    const paused = new Uint8Array(memory.buffer, world.build_queue_paused(), count);
+
+    const items: string[] = [];
+    for (let i = 0; i < count; i++) {
+        const name = world.query_tower_name(towerTypes[i]) + (upgrades[i] ? ' upgrade' : '');
+        const percent = Math.floor(100 * progress[i] / costs[i]);
+        const eta = Number.isFinite(etas[i]) ? `${Math.ceil(etas[i] / 60)}s` : '–';
+        items.push(`${name}|${percent}|${eta}|${paused[i]}`);
+    }
+    const rendered = items.join('\n');
+    if (rendered === lastRendered) {
+        return;
+    }
+    lastRendered = rendered;
+
+    list.textContent = '';
+    items.forEach((item, i) => {
+        const [name, percent, eta, isPaused] = item.split('|');
+        const li = document.createElement('li');
+        li.textContent = `${name} ${percent}% · ${eta} `;
+        if (isPaused === '1') {
+            li.classList.add('paused');
+        }
+        for (const [action, label] of [['front', '↑'], ['back', '↓'], ['pause', isPaused === '1' ? '▶' : '⏸']]) {
+            const button = document.createElement('button');
+            button.dataset.index = String(i);
+            button.dataset.action = action;
+            button.textContent = label;
+            li.appendChild(button);
+        }
+        list.appendChild(li);
+    });
+}
@@ -0,0 +1,47 @@
+// Mirrors EventType in backend/src/events.rs
+export enum EventType {
+    EnemySpawned,
+    EnemyKilled,
+    EnemyLeaked,
+    TowerCompleted,
+    UpgradeCompleted,
+    WaveStarted,
+    WaveCleared,
+    GameOver,
+}
+
+export interface GameEvent {
+    type: EventType;
+    entity: number;
+    x: number;
+    y: number;
+    // Meaning depends on the event type. See backend/src/events.rs
+    value: number;
+}
+
+type Listener = (event: GameEvent) => void;
+
+const listeners: Map<EventType, Listener[]> = new Map();
+
+export function onGameEvent(type: EventType, listener: Listener): void {
+    const list = listeners.get(type) || [];
+    list.push(listener);
+    listeners.set(type, list);
+}
+
+// Events queue up in the backend until drained, so this should be called
+// every frame even if nobody is listening.
+export function dispatchEvents(world: any, memory: WebAssembly.Memory): void {
+    const count = world.drain_events();
+    const types = new Uint8Array(memory.buffer, world.event_type(), count);
+    const entities = new Uint32Array(memory.buffer, world.event_entity(), count);
+    const xs = new Float32Array(memory.buffer, world.event_x(), count);
+    const ys = new Float32Array(memory.buffer, world.event_y(), count);
+    const values = new Float32Array(memory.buffer, world.event_value(), count);
+    for (let i = 0; i < count; i++) {
+        const event = { type: types[i], entity: entities[i], x: xs[i], y: ys[i], value: values[i] };
+        for (const listener of listeners.get(event.type) || []) {
+            listener(event);
+        }
+    }
+}
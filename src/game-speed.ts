@@ -59,7 +59,7 @@ window.addEventListener('keydown', event => {
     if (!event.repeat) {
         switch (event.code) {
             case 'KeyH':
-                bufferInput({ type: 'skip back' });
+                bufferInput({ type: event.shiftKey ? 'skip forward' : 'skip back' });
                 event.preventDefault();
                 break;
             case 'KeyJ':
@@ -79,16 +79,24 @@ window.addEventListener('keydown', event => {
     }
 });
 
-export function renderWaveDesc(waveIndex: number, ticksTillWave: number): void {
+export function renderWaveDesc(
+    waveIndex: number,
+    ticksTillWave: number,
+    waitingForClear: boolean,
+    lives: number,
+): void {
     let text = '';
     if (waveIndex < 0) {
         text = '';
+    } else if (waitingForClear) {
+        text = `Wave ${waveIndex + 1} arrives once the field is clear`;
     } else if (ticksTillWave > 0) {
         const seconds = Math.ceil(ticksTillWave / 60);
         text = `Wave ${waveIndex + 1} arrives in ${seconds}s`;
     } else {
         text = `Sending wave ${waveIndex + 1}`;
     }
+    text += ` · ${lives} ${lives === 1 ? 'life' : 'lives'}`;
     if (text !== waveDesc.textContent) {
         waveDesc.textContent = text;
     }
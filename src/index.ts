@@ -1,14 +1,17 @@
 /* eslint-disable @typescript-eslint/no-unsafe-member-access */
-import { Container, Filter, Graphics, Loader, ParticleContainer, Point, Renderer, SimpleRope, Sprite, Texture, Ticker } from 'pixi.js';
+import { Container, Filter, Graphics, Loader, ParticleContainer, Point, Renderer, SimpleRope, Sprite, Text, Texture, Ticker } from 'pixi.js';
 import { MAP_WIDTH, TILE_SIZE, MAP_HEIGHT, MS_PER_UPDATE, MAX_UPDATES_PER_FRAME } from './constants';
+import { renderBuildQueue } from './build-queue';
+import { dispatchEvents } from './events';
 import { executeToggleSpeed, gameSpeed, renderPlayPause, renderWaveDesc } from './game-speed';
 import { initGridInput, inputAvailable, localInputBuffer, mouseCol, mouseRow } from './input';
 import { drawGrid, initPathRendering } from './render/grid';
+import { renderHealthBar } from './render/health-bar';
 import { renderProgress } from './render/radial-progress';
 import { initRangeRendering, renderRange, setPreviewTowerInfo } from './render/range';
 import './settings';
 import './tower-select';
-import { clickedTower, hoveredTower, renderTowerSelect, selectedTowerIsDirty } from './tower-select';
+import { clickedTower, hoveredTower, renderSynergies, renderTowerSelect, selectedTowerIsDirty } from './tower-select';
 import './ice';
 import { shieldTexture } from './ice';
 
@@ -115,6 +118,9 @@ loader
         s.y = 400;
         // stage.addChild(s);
 
+        const hazardLayer = new Container();
+        stage.addChild(hazardLayer);
+
         const towerLayer = new Container();
         stage.addChild(towerLayer);
 
@@ -130,6 +136,9 @@ loader
         const progressLayer = new Container();
         stage.addChild(progressLayer);
 
+        const healthLayer = new Container();
+        stage.addChild(healthLayer);
+
         const smokeLayer = new Container();
         stage.addChild(smokeLayer);
 
@@ -273,8 +282,23 @@ loader
 
             const sprites: Sprite[] = [];
             const buildProgress: Graphics[] = [];
-
-            const world = worldModule.World.new(resources.config?.data || '');
+            const healthBars: Graphics[] = [];
+            const damageNumbers: Text[] = [];
+
+            // Difficulties are picked by name, like ?difficulty=Hard
+            const difficulty = new URLSearchParams(window.location.search).get('difficulty') || '';
+            const world = worldModule.World.new(resources.config?.data || '', difficulty);
+
+            // The hazard field is drawn as a low resolution texture that gets
+            // stretched over the map
+            const hazardWidth = world.hazard_width();
+            const hazardHeight = world.hazard_height();
+            const hazardPixels = new Uint8Array(4 * hazardWidth * hazardHeight);
+            const hazardTexture = Texture.fromBuffer(hazardPixels, hazardWidth, hazardHeight);
+            const hazardSprite = new Sprite(hazardTexture);
+            hazardSprite.width = MAP_WIDTH * TILE_SIZE;
+            hazardSprite.height = MAP_HEIGHT * TILE_SIZE;
+            hazardLayer.addChild(hazardSprite);
 
             function render(frameFudge: number) {
                 world.dump_sprite_data(frameFudge);
@@ -353,6 +377,12 @@ loader
                             sprite.height = (spriteAlphas[i] * 0.3 + 0.7) * TILE_SIZE;
                             sprite.anchor.set(0.5, 0.5);
                             break;
+                        case 9: // enemy shield
+                            sprite.texture = circleTexture;
+                            sprite.width = 0.8 * TILE_SIZE;
+                            sprite.height = 0.8 * TILE_SIZE;
+                            sprite.anchor.set(0.5, 0.5);
+                            break;
                     }
                     sprite.x = spriteXs[i];
                     sprite.y = spriteYs[i];
@@ -364,6 +394,19 @@ loader
                     sprites[i].visible = false;
                 }
 
+                world.dump_hazard();
+
+                const hazard = new Uint8Array(memModule.memory.buffer, world.hazard_texture(), hazardWidth * hazardHeight);
+                for (let i = 0; i < hazard.length; i++) {
+                    // Premultiplied alpha, so the color fades out with the density
+                    const alpha = hazard[i] * 0.6;
+                    hazardPixels[4 * i] = alpha;
+                    hazardPixels[4 * i + 1] = alpha * 0.4;
+                    hazardPixels[4 * i + 2] = 0;
+                    hazardPixels[4 * i + 3] = alpha;
+                }
+                hazardTexture.baseTexture.update();
+
                 world.dump_progress_data(frameFudge);
 
                 const progressCount = world.progress_count();
@@ -387,6 +430,58 @@ loader
                     buildProgress[i].visible = false;
                 }
 
+                world.dump_health_bars(frameFudge);
+
+                const healthBarCount = world.health_bar_count();
+                const healthBarXs = new Float32Array(memModule.memory.buffer, world.health_bar_x(), healthBarCount);
+                const healthBarYs = new Float32Array(memModule.memory.buffer, world.health_bar_y(), healthBarCount);
+                const healthBarVals = new Float32Array(memModule.memory.buffer, world.health_bar(), healthBarCount);
+
+                for (let i = healthBars.length; i < healthBarCount; i++) {
+                    const graphics = new Graphics();
+                    healthBars.push(graphics);
+                    healthLayer.addChild(graphics);
+                }
+                for (let i = 0; i < healthBarCount; i++) {
+                    const graphics = healthBars[i];
+                    graphics.visible = true;
+                    renderHealthBar(graphics, healthBarVals[i]);
+                    graphics.x = healthBarXs[i];
+                    graphics.y = healthBarYs[i];
+                }
+                for (let i = healthBarCount; i < healthBars.length; i++) {
+                    healthBars[i].visible = false;
+                }
+
+                world.dump_damage_numbers(frameFudge);
+
+                const damageNumberCount = world.damage_number_count();
+                const damageNumberXs = new Float32Array(memModule.memory.buffer, world.damage_number_x(), damageNumberCount);
+                const damageNumberYs = new Float32Array(memModule.memory.buffer, world.damage_number_y(), damageNumberCount);
+                const damageNumberVals = new Float32Array(memModule.memory.buffer, world.damage_number(), damageNumberCount);
+                const damageNumberAlphas = new Float32Array(memModule.memory.buffer, world.damage_number_alpha(), damageNumberCount);
+
+                for (let i = damageNumbers.length; i < damageNumberCount; i++) {
+                    const text = new Text('', { fontSize: 10, fill: 0xffffff, stroke: 0x000000, strokeThickness: 2 });
+                    text.anchor.set(0.5, 1);
+                    damageNumbers.push(text);
+                    healthLayer.addChild(text);
+                }
+                for (let i = 0; i < damageNumberCount; i++) {
+                    const text = damageNumbers[i];
+                    text.visible = true;
+                    const label = String(Math.round(damageNumberVals[i]));
+                    if (text.text !== label) {
+                        text.text = label;
+                    }
+                    text.x = damageNumberXs[i];
+                    text.y = damageNumberYs[i];
+                    text.alpha = damageNumberAlphas[i];
+                }
+                for (let i = damageNumberCount; i < damageNumbers.length; i++) {
+                    damageNumbers[i].visible = false;
+                }
+
                 renderRange(world.range_cx(), world.range_cy(), world.range_radius());
             }
 
@@ -420,10 +515,14 @@ loader
                     // Base range is measured in tiles, not pixels
                     setPreviewTowerInfo(TILE_SIZE * world.query_tower_base_range(clickedTower.towerIndex),
                         // eslint-disable-next-line @typescript-eslint/no-unsafe-call
-                        world.query_can_build_tower(mouseRow, mouseCol));
+                        world.query_can_build_tower(mouseRow, mouseCol, clickedTower.towerIndex));
+                    const synergyCount = world.preview_synergies(mouseRow, mouseCol, clickedTower.towerIndex);
+                    const synergyIndices = new Uint32Array(memModule.memory.buffer, world.synergy_preview(), synergyCount);
+                    renderSynergies(Array.from(synergyIndices, i => world.query_synergy_description(i)));
                 } else {
                     world.hide_preview_tower();
                     setPreviewTowerInfo(0, true);
+                    renderSynergies([]);
                 }
 
                 if (selectedTowerIsDirty) {
@@ -454,7 +553,13 @@ loader
                 }
 
                 renderPlayPause(world.run_state());
-                renderWaveDesc(world.next_wave_index(), world.ticks_till_next_wave());
+                renderBuildQueue(world, memModule.memory);
+                renderWaveDesc(
+                    world.next_wave_index(),
+                    world.ticks_till_next_wave(),
+                    world.waiting_for_clear(),
+                    world.query_lives(),
+                );
 
                 const msPerUpdate = MS_PER_UPDATE / gameSpeed();
 
@@ -474,6 +579,9 @@ loader
                             case 'skip back':
                                 world.restore();
                                 break;
+                            case 'skip forward':
+                                world.redo();
+                                break;
                             case 'play pause':
                                 world.play_pause();
                                 break;
@@ -486,6 +594,35 @@ loader
                             case 'cancel tower':
                                 world.cancel_construction(input.row, input.col);
                                 break;
+                            case 'upgrade tower':
+                                world.queue_upgrade(input.row, input.col, input.upgradeIndex);
+                                break;
+                            case 'build line':
+                                world.clear_blueprint();
+                                world.add_line_to_blueprint(input.fromRow, input.fromCol, input.toRow, input.toCol, input.towerIndex);
+                                world.queue_blueprint();
+                                break;
+                            case 'build tesla straddle':
+                                world.clear_blueprint();
+                                if (world.add_tesla_straddle_to_blueprint(input.row, input.col)) {
+                                    world.queue_blueprint();
+                                }
+                                break;
+                            case 'move build order':
+                                if (input.to === 'front') {
+                                    world.move_build_order_to_front(input.index);
+                                } else {
+                                    world.move_build_order_to_back(input.index);
+                                }
+                                break;
+                            case 'toggle build order paused':
+                                world.toggle_build_order_paused(input.index);
+                                break;
+                            case 'cycle factory mode': {
+                                const mode = world.query_factory_mode(world.query_tower_entity(input.row, input.col));
+                                world.set_factory_mode(input.row, input.col, (mode + 1) % 3);
+                                break;
+                            }
                         }
                     }
 
@@ -498,6 +635,8 @@ loader
                     }
                 }
 
+                dispatchEvents(world, memModule.memory);
+
                 filter.uniforms.customUniform += 0.02;
                 filter.uniforms.customUniform %= 3.0;
 
@@ -30,6 +30,8 @@ type Input = {
     towerIndex: number,
 } | {
     type: 'skip back',
+} | {
+    type: 'skip forward',
 } | {
     type: 'play pause',
 } | {
@@ -40,6 +42,33 @@ type Input = {
     type: 'cancel tower',
     row: number,
     col: number,
+} | {
+    type: 'upgrade tower',
+    row: number,
+    col: number,
+    upgradeIndex: number,
+} | {
+    type: 'cycle factory mode',
+    row: number,
+    col: number,
+} | {
+    type: 'build line',
+    fromRow: number,
+    fromCol: number,
+    toRow: number,
+    toCol: number,
+    towerIndex: number,
+} | {
+    type: 'build tesla straddle',
+    row: number,
+    col: number,
+} | {
+    type: 'move build order',
+    index: number,
+    to: 'front' | 'back',
+} | {
+    type: 'toggle build order paused',
+    index: number,
 };
 
 // NonSyncInputs do not affect core game state, so they do not need to be
@@ -73,10 +102,36 @@ export function initGridInput(canvas: HTMLElement): void {
         }
     });
 
+    // Shift-dragging builds a line of towers
+    let dragStart: [number, number] | undefined;
+    canvas.addEventListener('mousedown', event => {
+        if (event.shiftKey && clickedTower?.towerStatus === 'prototype') {
+            dragStart = [Math.floor(event.offsetY / TILE_SIZE), Math.floor(event.offsetX / TILE_SIZE)];
+        }
+    });
+
+    canvas.addEventListener('mouseup', event => {
+        if (dragStart && clickedTower?.towerStatus === 'prototype') {
+            bufferInput({
+                type: 'build line',
+                fromRow: dragStart[0],
+                fromCol: dragStart[1],
+                toRow: Math.floor(event.offsetY / TILE_SIZE),
+                toCol: Math.floor(event.offsetX / TILE_SIZE),
+                towerIndex: clickedTower.towerIndex,
+            });
+        }
+        dragStart = undefined;
+    });
+
     canvas.addEventListener('click', event => {
         const row = Math.floor(event.offsetY / TILE_SIZE);
         const col = Math.floor(event.offsetX / TILE_SIZE);
         if (clickedTower?.towerStatus === 'prototype') {
+            // Shift-clicks were already handled as lines
+            if (event.shiftKey) {
+                return;
+            }
             // Try and build a new tower
             bufferInput({
                 type: 'build tower',
@@ -94,6 +149,35 @@ export function initGridInput(canvas: HTMLElement): void {
         }
     });
 
+    // Number keys queue upgrades for the hovered tower, F switches what a
+    // hovered factory does, and T puts teslas on either side of the nearby
+    // path
+    window.addEventListener('keydown', event => {
+        const match = /^Digit([1-8])$/.exec(event.code);
+        if (match && !event.repeat && mouseRow >= 0 && mouseCol >= 0) {
+            bufferInput({
+                type: 'upgrade tower',
+                row: mouseRow,
+                col: mouseCol,
+                upgradeIndex: Number(match[1]) - 1,
+            });
+        }
+        if (event.code === 'KeyT' && !event.repeat && mouseRow >= 0 && mouseCol >= 0) {
+            bufferInput({
+                type: 'build tesla straddle',
+                row: mouseRow,
+                col: mouseCol,
+            });
+        }
+        if (event.code === 'KeyF' && !event.repeat && mouseRow >= 0 && mouseCol >= 0) {
+            bufferInput({
+                type: 'cycle factory mode',
+                row: mouseRow,
+                col: mouseCol,
+            });
+        }
+    });
+
     canvas.addEventListener('contextmenu', event => {
         const row = Math.floor(event.offsetY / TILE_SIZE);
         const col = Math.floor(event.offsetX / TILE_SIZE);
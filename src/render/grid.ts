@@ -69,6 +69,20 @@ export function drawGrid(container: Container): void {
 }
 
 export function initPathRendering(container: Container): void {
+    // Kept so that the map can be drawn again after it is edited
+    const mapSprites: Sprite[] = [];
+    function addMapSprite(sprite: Sprite): void {
+        mapSprites.push(sprite);
+        container.addChild(sprite);
+    }
+
+    (window as any).clear_map = function (): void {
+        for (const sprite of mapSprites) {
+            sprite.destroy();
+        }
+        mapSprites.length = 0;
+    };
+
     (window as any).render_path_tile = function (row: number, col: number): void {
         const square = Sprite.from(Texture.WHITE);
         square.tint = 0xE8E8E8;
@@ -76,7 +90,21 @@ export function initPathRendering(container: Container): void {
         square.height = TILE_SIZE + 1;
         square.x = col * TILE_SIZE;
         square.y = row * TILE_SIZE;
-        container.addChild(square);
+        addMapSprite(square);
+    };
+
+    // Indexed like the Terrain enum in map.rs, starting from rocks since plain
+    // terrain isn't drawn
+    const terrainTints = [0x999999, 0xE6DDB8, 0xB89F7A, 0xA8D0F0, 0xC8B48C];
+
+    (window as any).render_terrain = function (row: number, col: number, terrain: number): void {
+        const square = Sprite.from(Texture.WHITE);
+        square.tint = terrainTints[terrain - 1];
+        square.width = TILE_SIZE + 1;
+        square.height = TILE_SIZE + 1;
+        square.x = col * TILE_SIZE;
+        square.y = row * TILE_SIZE;
+        addMapSprite(square);
     };
 
     (window as any).render_path_border = function (row: number, col: number, horizontal: boolean): void {
@@ -87,7 +115,7 @@ export function initPathRendering(container: Container): void {
             edge.height = 1;
             edge.x = col * TILE_SIZE;
             edge.y = row * TILE_SIZE;
-            container.addChild(edge);
+            addMapSprite(edge);
         } else {
             const edge = Sprite.from(Texture.WHITE);
             edge.tint = 0x000000;
@@ -95,7 +123,7 @@ export function initPathRendering(container: Container): void {
             edge.height = TILE_SIZE + 1;
             edge.x = col * TILE_SIZE;
             edge.y = row * TILE_SIZE;
-            container.addChild(edge);
+            addMapSprite(edge);
         }
     };
 }
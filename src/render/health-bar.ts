@@ -0,0 +1,17 @@
+import { Graphics } from 'pixi.js';
+import { TILE_SIZE } from '../constants';
+
+const WIDTH = TILE_SIZE * 0.8;
+const HEIGHT = 3;
+const OFFSET_Y = -TILE_SIZE * 0.6;
+
+export function renderHealthBar(graphics: Graphics, health: number): void {
+    graphics.clear();
+    graphics.lineStyle(0);
+    graphics.beginFill(0x000000, 0.6);
+    graphics.drawRect(-WIDTH / 2, OFFSET_Y, WIDTH, HEIGHT);
+    graphics.endFill();
+    graphics.beginFill(health > 0.3 ? 0x55cc55 : 0xcc5555, 1);
+    graphics.drawRect(-WIDTH / 2, OFFSET_Y, WIDTH * health, HEIGHT);
+    graphics.endFill();
+}
@@ -75,13 +75,13 @@ const towerTitleBg = document.querySelector('#tower-stats .tower-name') as HTMLD
 const towerCost = document.getElementById('tower-cost') as HTMLSpanElement;
 const damageVal = document.querySelector('#damage .val') as HTMLSpanElement;
 const damageBar = document.querySelector('#damage .barline') as HTMLDivElement;
-const rateOfFireLabel = document.getElementById('rate-of-fire-title') as HTMLSpanElement;
 const rateOfFireVal = document.querySelector('#rate-of-fire .val') as HTMLSpanElement;
 const rateOfFireBar = document.querySelector('#rate-of-fire .barline') as HTMLDivElement;
 const rangeVal = document.querySelector('#range .val') as HTMLSpanElement;
 const rangeBar = document.querySelector('#range .barline') as HTMLDivElement;
 const description = document.getElementById('description') as HTMLParagraphElement;
 const flavor = document.getElementById('flavor') as HTMLElement;
+const synergies = document.getElementById('synergies') as HTMLParagraphElement;
 
 export function renderTowerSelect(
     title: string,
@@ -107,7 +107,6 @@ export function renderTowerSelect(
     if (title === 'Fire')
         damageVal.textContent += 'Hz';
     damageBar.style.background = formatGradient(damage, 0, 100);
-    rateOfFireLabel.textContent = title === 'Swallow' ? 'Airspeed' : 'Rate of fire';
     rateOfFireVal.textContent = formatFloat(rateOfFire);
     rateOfFireBar.style.background = formatGradient(rateOfFire, 0, 100);
     rangeVal.textContent = formatFloat(range);
@@ -116,6 +115,14 @@ export function renderTowerSelect(
     flavor.textContent = flavorStr;
 }
 
+// Synergies that the tower being placed would activate with its neighbors
+export function renderSynergies(descriptions: string[]): void {
+    const text = descriptions.join(' ');
+    if (text !== synergies.textContent) {
+        synergies.textContent = text;
+    }
+}
+
 export function cancelTowerSelect(): void {
     clickedTower = undefined;
     hoveredTower = undefined;